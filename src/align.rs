@@ -0,0 +1,186 @@
+use itertools::Itertools;
+use srtlib::Subtitle;
+
+use crate::html;
+
+/// Share of a line's characters that must match the book for its text to be used.
+const MIN_CONFIDENCE: f64 = 0.6;
+/// Characters of the book searched after the previous match, at least.
+const MIN_WINDOW: usize = 2000;
+/// Lines missed in a row before the whole book is searched for the next one.
+const MAX_MISSES: usize = 5;
+/// Confidence needed to move the cursor anywhere in the book.
+const REANCHOR_CONFIDENCE: f64 = 0.8;
+/// Lines searched near the cursor again after a failed search of the whole book, which
+/// is slow, before the next one.
+const REANCHOR_INTERVAL: usize = 10;
+/// Punctuation that opens a quote or aside, kept with the text after it.
+const OPENING: &[char] = &['「', '『', '（', '(', '“', '‘', '[', '【', '〈', '《'];
+
+/// Letters and digits of the text, lowercased, with the index each came from.
+fn normalize(text: &[char]) -> (Vec<char>, Vec<usize>) {
+    text.iter()
+        .enumerate()
+        .filter(|(_, c)| c.is_alphanumeric())
+        .flat_map(|(i, c)| c.to_lowercase().map(move |c| (c, i)))
+        .unzip()
+}
+
+/// Where `needle` best fits in `haystack`, as start, end and edit distance. Skipping
+/// the haystack before and after the match is free, so this finds the substring
+/// closest to the needle while tolerating insertions and deletions.
+fn best_match(needle: &[char], haystack: &[char]) -> Option<(usize, usize, usize)> {
+    if needle.is_empty() || haystack.is_empty() {
+        return None;
+    }
+    // (cost, start of the match) for every end in the haystack.
+    let mut prev: Vec<(usize, usize)> = (0..=haystack.len()).map(|j| (0, j)).collect();
+    for (i, n) in needle.iter().enumerate() {
+        let mut row = Vec::with_capacity(haystack.len() + 1);
+        row.push((i + 1, 0));
+        for (j, h) in haystack.iter().enumerate() {
+            let replace = (prev[j].0 + usize::from(n != h), prev[j].1);
+            let skip_needle = (prev[j + 1].0 + 1, prev[j + 1].1);
+            let skip_haystack = (row[j].0 + 1, row[j].1);
+            row.push(replace.min(skip_needle).min(skip_haystack));
+        }
+        prev = row;
+    }
+    let (end, &(cost, start)) = prev.iter().enumerate().min_by_key(|(j, (c, _))| (*c, *j))?;
+    Some((start, end, cost))
+}
+
+/// The book's text between two characters, with the punctuation around it.
+fn span(original: &[char], mut from: usize, mut to: usize) -> String {
+    while from > 0 && OPENING.contains(&original[from - 1]) {
+        from -= 1;
+    }
+    while to < original.len()
+        && !original[to].is_alphanumeric()
+        && !original[to].is_whitespace()
+        && !OPENING.contains(&original[to])
+    {
+        to += 1;
+    }
+    original[from..to]
+        .iter()
+        .collect::<String>()
+        .split_whitespace()
+        .join(" ")
+}
+
+/// The book's text for each line, found in reading order after the previous line's.
+/// After several misses in a row, the whole book is searched so that a skipped passage
+/// or a wrong match does not lose the following lines, and again every few lines while
+/// nothing matches.
+/// None when the line could not be matched confidently, so it keeps its own text.
+pub fn book_text(lines: &[Subtitle], book: &str) -> Vec<Option<String>> {
+    let original: Vec<char> = book.chars().collect();
+    let (chars, at) = normalize(&original);
+    let mut cursor = 0;
+    let mut misses = 0;
+    lines
+        .iter()
+        .map(|line| {
+            let (needle, _) = normalize(&html::plain(&line.text).chars().collect::<Vec<char>>());
+            if needle.is_empty() {
+                return None;
+            }
+            let reanchor = misses >= MAX_MISSES && (misses - MAX_MISSES) % REANCHOR_INTERVAL == 0;
+            let (from, to, min_confidence) = if reanchor {
+                (0, chars.len(), REANCHOR_CONFIDENCE)
+            } else {
+                let window = (needle.len() * 20).max(MIN_WINDOW);
+                (cursor, (cursor + window).min(chars.len()), MIN_CONFIDENCE)
+            };
+            let found = best_match(&needle, &chars[from..to]).filter(|&(start, stop, cost)| {
+                1.0 - cost as f64 / needle.len() as f64 >= min_confidence && stop > start
+            });
+            let Some((start, stop, _)) = found else {
+                misses += 1;
+                return None;
+            };
+            misses = 0;
+            let (start, stop) = (from + start, from + stop);
+            cursor = stop;
+            Some(span(&original, at[start], at[stop - 1] + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::test_line;
+
+    fn lines(texts: &[&str]) -> Vec<Subtitle> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| test_line(i + 1, 0, 1000, t))
+            .collect()
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn best_match_tolerates_edits() {
+        assert_eq!(
+            best_match(&chars("abc"), &chars("xxabcxx")),
+            Some((2, 5, 0))
+        );
+        assert_eq!(
+            best_match(&chars("abxd"), &chars("zzabcdzz")),
+            Some((2, 6, 1))
+        );
+        assert_eq!(best_match(&chars("abc"), &chars("")), None);
+    }
+
+    #[test]
+    fn book_text_follows_reading_order() {
+        let book = "「First line here.」 Second one\nthere! Third and last.";
+        assert_eq!(
+            book_text(
+                &lines(&["first line here", "second one there", "third and last"]),
+                book
+            ),
+            vec![
+                Some(String::from("「First line here.」")),
+                Some(String::from("Second one there!")),
+                Some(String::from("Third and last.")),
+            ]
+        );
+    }
+
+    #[test]
+    fn book_text_ignores_markup() {
+        let book = "The quick brown fox.";
+        assert_eq!(
+            book_text(&lines(&["<i>the quick</i> {\\an8}brown fox"]), book),
+            vec![Some(String::from("The quick brown fox."))]
+        );
+    }
+
+    #[test]
+    fn book_text_reanchors_after_misses() {
+        let book = format!(
+            "Start here. {}The target sentence is found.",
+            "abc ".repeat(2000)
+        );
+        let texts = [
+            "start here",
+            "xyz xyz",
+            "xyz xyz",
+            "xyz xyz",
+            "xyz xyz",
+            "xyz xyz",
+            "the target sentence is found",
+        ];
+        let found = book_text(&lines(&texts), &book);
+        assert_eq!(found[0].as_deref(), Some("Start here."));
+        assert!(found[1..6].iter().all(Option::is_none));
+        assert_eq!(found[6].as_deref(), Some("The target sentence is found."));
+    }
+}
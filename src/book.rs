@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Component, Path, PathBuf},
+};
+
+use epub::doc::{EpubDoc, NavPoint};
+use scraper::{ElementRef, Html, Node, Selector};
+
+use crate::probe::Chapter;
+
+/// Part of the book between two table of contents entries.
+#[derive(Debug, Default)]
+pub struct Section {
+    pub title: String,
+    /// Number of characters of text in the section.
+    pub len: usize,
+    /// Images of the section, with the number of characters read before them.
+    pub images: Vec<(usize, PathBuf)>,
+}
+
+/// A ruby annotation, in reading order.
+#[derive(Debug, Clone)]
+pub struct Ruby {
+    pub base: String,
+    pub reading: String,
+    /// Text of the paragraph the annotation is in, readings excluded.
+    pub context: String,
+}
+
+pub struct Book {
+    doc: EpubDoc<BufReader<File>>,
+    pub sections: Vec<Section>,
+    pub rubies: Vec<Ruby>,
+    /// Everything that is read, in order, with a line break before each block.
+    pub text: String,
+}
+
+/// Elements whose text is reading aid rather than book text.
+const ANNOTATIONS: &[&str] = &["rt", "rp"];
+/// Elements a ruby's context is taken from.
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "li",
+    "td",
+    "blockquote",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+
+/// Text of the element, split into what is read and what annotates it.
+fn split_text(element: ElementRef) -> (String, String) {
+    let mut text = String::new();
+    let mut annotations = String::new();
+    for node in element.descendants() {
+        if let Node::Text(t) = node.value() {
+            let annotation = node.ancestors().any(|a| {
+                a.value()
+                    .as_element()
+                    .is_some_and(|e| ANNOTATIONS.contains(&e.name()))
+            });
+            if annotation {
+                annotations.push_str(t.trim());
+            } else {
+                text.push_str(t);
+            }
+        }
+    }
+    (text, annotations)
+}
+
+fn flatten_toc(points: &[NavPoint], out: &mut Vec<(PathBuf, String)>) {
+    for point in points {
+        let content = point.content.to_string_lossy();
+        let path = content.split('#').next().unwrap_or_default();
+        out.push((PathBuf::from(path), point.label.trim().to_string()));
+        flatten_toc(&point.children, out);
+    }
+}
+
+/// Resolves a link found in the document at `base` into a path inside the archive.
+fn resolve(base: &Path, href: &str) -> PathBuf {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut path = PathBuf::new();
+    for component in base
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(href)
+        .components()
+    {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => {}
+            c => path.push(c),
+        }
+    }
+    path
+}
+
+fn normalize(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl Book {
+    pub fn open(path: &Path) -> Result<Book, String> {
+        let mut doc = EpubDoc::new(path).map_err(|e| e.to_string())?;
+        let mut toc = Vec::new();
+        flatten_toc(&doc.toc, &mut toc);
+        let titles: HashMap<PathBuf, String> = toc.into_iter().rev().collect();
+
+        let mut sections = vec![Section::default()];
+        let mut rubies = Vec::new();
+        let mut text = String::new();
+        let body = Selector::parse("body").unwrap();
+        for page in 0..doc.get_num_pages() {
+            doc.set_current_page(page);
+            let Some(page_path) = doc.get_current_path() else {
+                continue;
+            };
+            let Some((content, _mime)) = doc.get_current_str() else {
+                continue;
+            };
+            if let Some(title) = titles.get(&page_path) {
+                sections.push(Section {
+                    title: title.clone(),
+                    ..Section::default()
+                });
+            }
+            let section = sections.last_mut().unwrap();
+            let html = Html::parse_document(&content);
+            // The head holds the page title and styles, which are not read.
+            let root = html.select(&body).next().unwrap_or(html.root_element());
+            for node in root.descendants() {
+                match node.value() {
+                    Node::Text(t) => {
+                        let in_rt = node.ancestors().any(|a| {
+                            a.value()
+                                .as_element()
+                                .is_some_and(|e| ANNOTATIONS.contains(&e.name()))
+                        });
+                        if !in_rt {
+                            section.len += t.chars().filter(|c| !c.is_whitespace()).count();
+                            text.push_str(t);
+                        }
+                    }
+                    Node::Element(e) if BLOCKS.contains(&e.name()) => text.push('\n'),
+                    Node::Element(e) if e.name() == "ruby" => {
+                        let Some(ruby) = ElementRef::wrap(node) else {
+                            continue;
+                        };
+                        let (base, reading) = split_text(ruby);
+                        let context = node
+                            .ancestors()
+                            .filter_map(ElementRef::wrap)
+                            .find(|a| BLOCKS.contains(&a.value().name()))
+                            .map_or_else(|| base.clone(), |block| split_text(block).0);
+                        if !base.trim().is_empty() && !reading.is_empty() {
+                            rubies.push(Ruby {
+                                base: base.trim().to_string(),
+                                reading,
+                                context,
+                            });
+                        }
+                    }
+                    Node::Element(e) if e.name() == "img" || e.name() == "image" => {
+                        // SVG links are namespaced, unless the parser turned the
+                        // element into an img.
+                        let href = e
+                            .attr("src")
+                            .or_else(|| e.attr("href"))
+                            .or_else(|| e.attr("xlink:href"));
+                        if let Some(href) = href {
+                            section
+                                .images
+                                .push((section.len, resolve(&page_path, href)));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        sections.retain(|s| s.len > 0 || !s.images.is_empty());
+        Ok(Book {
+            doc,
+            sections,
+            rubies,
+            text,
+        })
+    }
+
+    pub fn resource(&mut self, path: &Path) -> Option<Vec<u8>> {
+        self.doc.get_resource_by_path(path)
+    }
+
+    /// Time range of the audio (in ms) read for each section. Sections are paired with
+    /// the audio chapters by order when there are as many of both, by title otherwise,
+    /// and spread over the whole audio by text length when titles do not match either.
+    pub fn section_spans(&self, chapters: &[Chapter], duration: u32) -> Vec<Option<(u32, u32)>> {
+        if chapters.len() == self.sections.len() {
+            return chapters.iter().map(|c| Some((c.start, c.end))).collect();
+        }
+        let by_title: Vec<Option<(u32, u32)>> = self
+            .sections
+            .iter()
+            .map(|s| {
+                let title = normalize(&s.title);
+                if title.is_empty() {
+                    return None;
+                }
+                chapters
+                    .iter()
+                    .find(|c| {
+                        let chapter = normalize(&c.title);
+                        !chapter.is_empty()
+                            && (chapter.contains(&title) || title.contains(&chapter))
+                    })
+                    .map(|c| (c.start, c.end))
+            })
+            .collect();
+        if by_title.iter().flatten().count() * 2 >= self.sections.len() {
+            return by_title;
+        }
+        let total = self.sections.iter().map(|s| s.len).sum::<usize>().max(1) as u64;
+        let mut read = 0;
+        self.sections
+            .iter()
+            .map(|s| {
+                let start = u64::from(duration) * read / total;
+                read += s.len as u64;
+                Some((start as u32, (u64::from(duration) * read / total) as u32))
+            })
+            .collect()
+    }
+
+    /// Every illustration with the time (in ms) it is reached in the audio, in order.
+    pub fn illustration_times(&self, chapters: &[Chapter], duration: u32) -> Vec<(u32, PathBuf)> {
+        let mut times: Vec<(u32, PathBuf)> = self
+            .sections
+            .iter()
+            .zip(self.section_spans(chapters, duration))
+            .filter_map(|(section, span)| span.map(|span| (section, span)))
+            .flat_map(|(section, (start, end))| {
+                section.images.iter().map(move |(offset, path)| {
+                    let into = u64::from(end.saturating_sub(start)) * *offset as u64
+                        / section.len.max(1) as u64;
+                    (start + into as u32, path.clone())
+                })
+            })
+            .collect();
+        times.sort_by_key(|(t, _)| *t);
+        times
+    }
+}
@@ -2,9 +2,10 @@
 // use getch::Getch;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 // use scraper::{Element, Selector};
+use itertools::Itertools;
 use srtlib::{Subtitle, Subtitles, Timestamp};
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
@@ -12,7 +13,13 @@ use std::{
         Arc,
     },
 };
-use itertools::Itertools;
+
+use crate::{
+    filter::{self, FilterRules},
+    probe,
+    silence::{self, Silence},
+    sync::Correction,
+};
 
 const CHUNK_SIZE: usize = 25;
 const SILENCE: &[u8] = include_bytes!("../silence.mp3");
@@ -23,54 +30,6 @@ const SILENCE: &[u8] = include_bytes!("../silence.mp3");
 //     format!("{}.{:0>3}", seconds_total, millis)
 // }
 
-// TODO if we implement that, change rubies from renpy format to anki format
-// fn replace_rubies(text: &mut String, rubies: &mut VecDeque<[String; 3]>) {
-//     if rubies.front().is_none() {
-//         return;
-//     }
-//     let mut queue: Vec<[String; 2]> = Vec::with_capacity(5);
-//     while textdistance::str::overlap(&rubies[0][2], text) > 0.5 && text.contains(&rubies[0][0]) {
-//         let front = rubies.pop_front().unwrap();
-//         queue.push([front[0].clone(), front[1].clone()]);
-//         if rubies.is_empty() {
-//             break;
-//         }
-//     }
-//     while let Some(replacement) = queue.pop() {
-//         *text = text.replace(
-//             &replacement[0].to_string(),
-//             &format!(
-//                 "{{rb}}{}{{/rb}}{{rt}}{}{{/rt}}",
-//                 replacement[0], replacement[1]
-//             ),
-//         );
-//     }
-// }
-// fn _replace_rubies_old(text: &mut String, rubies: &mut VecDeque<[String; 3]>) {
-//     if rubies.front().is_none() {
-//         return;
-//     }
-//     let mut queue: Vec<[String; 2]> = Vec::with_capacity(5);
-//     while textdistance::str::overlap(&rubies[0][2], text) > 0.5
-//         && text.contains(&format!("{}{}", &rubies[0][0], &rubies[0][1]))
-//     {
-//         let front = rubies.pop_front().unwrap();
-//         queue.push([front[0].clone(), front[1].clone()]);
-//         if rubies.is_empty() {
-//             break;
-//         }
-//     }
-//     while let Some(replacement) = queue.pop() {
-//         *text = text.replace(
-//             &format!("{}{}", replacement[0], replacement[1]),
-//             &format!(
-//                 "{{rb}}{}{{/rb}}{{rt}}{}{{/rt}}",
-//                 replacement[0], replacement[1]
-//             ),
-//         );
-//     }
-// }
-
 fn prepare_ffmpeg_command(
     start: usize,
     count: usize,
@@ -95,22 +54,34 @@ fn prepare_ffmpeg_command(
             std::fs::write(&path, SILENCE).unwrap();
             continue;
         }
-        r.extend(
-            [
-                "-c",
-                "copy",
-                "-ss",
-                &s[i].start_time.to_string().replace(',', "."),
-                "-to",
-                &s[i].end_time.to_string().replace(',', "."),
-                &path_str,
-            ]
-            .map(|s| s.to_string()),
-        )
+        r.extend(clip_args(&s[i], &path_str))
     }
     r
 }
 
+/// ffmpeg output arguments cutting the line's time range out of the input into `output`.
+pub fn clip_args(sub: &Subtitle, output: &str) -> [String; 7] {
+    [
+        "-c",
+        "copy",
+        "-ss",
+        &sub.start_time.to_string().replace(',', "."),
+        "-to",
+        &sub.end_time.to_string().replace(',', "."),
+        output,
+    ]
+    .map(|s| s.to_string())
+}
+
+/// Clips ready to be cut, each with the srt line it was made from.
+#[derive(Debug, Clone, Default)]
+pub struct Lines {
+    /// Numbered from 1 in order, `num - 1` being the clip index.
+    pub clips: Vec<Subtitle>,
+    /// The srt line of each clip, as it is in the file. Parts of a split line share it.
+    pub originals: Vec<Subtitle>,
+}
+
 #[derive(Debug)]
 pub struct MyArgs {
     pub audiobook: PathBuf,
@@ -121,9 +92,347 @@ pub struct MyArgs {
     // pub show_buggies: bool,
     pub start_offset: i32,
     pub end_offset: i32,
+    /// Lines whose clip is longer than this (in ms) get split at sentence punctuation.
+    pub max_length: Option<u32>,
+    pub split_on_silence: bool,
+    /// Clip boundaries move to the closest pause found within this many ms.
+    pub snap_window: Option<u32>,
+    /// At most this many ms are kept after a line's own end when extending it to the next line.
+    pub max_tail: Option<u32>,
+    pub trim_silence: bool,
+    /// Offset and drift applied to the subtitle timestamps before anything else.
+    pub correction: Option<Correction>,
+    /// Lines edited in the preview, used as they are instead of the srt.
+    pub lines: Option<Lines>,
+    pub filters: FilterRules,
+}
+
+/// Inputs that get converted to an mp3 next to them before being cut.
+const CONVERTED_EXTENSIONS: &[&str] = &["m4b", "mkv", "mp4", "webm"];
+
+pub fn needs_conversion(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        CONVERTED_EXTENSIONS
+            .iter()
+            .any(|c| ext.eq_ignore_ascii_case(c))
+    })
+}
+
+pub fn timestamp_to_ms(t: &Timestamp) -> u32 {
+    let (hours, mins, secs, millis) = t.get();
+    ((u32::from(hours) * 60 + u32::from(mins)) * 60 + u32::from(secs)) * 1000 + u32::from(millis)
+}
+
+pub fn ms_to_timestamp(ms: u32) -> Timestamp {
+    Timestamp::new(
+        (ms / 3_600_000) as u8,
+        (ms / 60_000 % 60) as u8,
+        (ms / 1000 % 60) as u8,
+        (ms % 1000) as u16,
+    )
+}
+
+/// A line numbered `num` spanning `start` to `end` ms, for the tests.
+#[cfg(test)]
+pub fn test_line(num: usize, start: u32, end: u32, text: &str) -> Subtitle {
+    Subtitle::new(
+        num,
+        ms_to_timestamp(start),
+        ms_to_timestamp(end),
+        text.to_string(),
+    )
+}
+
+const SENTENCE_ENDS: &[char] = &['。', '！', '？', '!', '?', '.', '…', '♪'];
+const CLAUSE_ENDS: &[char] = &['、', '，', ',', '；', ';', '：', ':'];
+const CLOSERS: &[char] = &['」', '』', '）', '】', '〉', '》', ')', '"', '\''];
+/// How far (in ms) a proportional cut may move to land on a pause.
+const SPLIT_SILENCE_WINDOW: u32 = 1500;
+
+fn split_text<'a>(text: &'a str, ends: &[char]) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut begin = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if !ends.contains(&c) {
+            continue;
+        }
+        while let Some(&(_, next)) = chars.peek() {
+            if ends.contains(&next) || CLOSERS.contains(&next) {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let end = chars.peek().map_or(text.len(), |&(i, _)| i);
+        parts.push(&text[begin..end]);
+        begin = end;
+    }
+    parts.push(&text[begin..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn visible_len(text: &str) -> u32 {
+    text.chars().filter(|c| !c.is_whitespace()).count().max(1) as u32
 }
 
-pub fn process(args: MyArgs, thread_tx: Sender<String>) {
+/// Breaks a line longer than `max_ms` into sentence-sized lines, sharing its time range
+/// proportionally to the number of characters of each part.
+fn split_long(sub: &Subtitle, max_ms: u32, silences: Option<&[Silence]>) -> Vec<Subtitle> {
+    let start = timestamp_to_ms(&sub.start_time);
+    let end = timestamp_to_ms(&sub.end_time);
+    let duration = end.saturating_sub(start);
+    if duration <= max_ms {
+        return vec![sub.clone()];
+    }
+    let mut parts = split_text(&sub.text, SENTENCE_ENDS);
+    if parts.len() < 2 {
+        parts = split_text(&sub.text, CLAUSE_ENDS);
+    }
+    if parts.len() < 2 {
+        return vec![sub.clone()];
+    }
+
+    let total: u32 = parts.iter().map(|p| visible_len(p)).sum();
+    let share = |chars: u32| (u64::from(duration) * u64::from(chars) / u64::from(total)) as u32;
+
+    // Group consecutive parts greedily so every group fits in max_ms.
+    let mut groups: Vec<(String, u32)> = Vec::new();
+    for part in parts {
+        let chars = visible_len(part);
+        match groups.last_mut() {
+            Some((text, len)) if share(*len + chars) <= max_ms => {
+                // Latin text was split on whitespace, put it back.
+                if text.ends_with(|c: char| c.is_ascii())
+                    && part.starts_with(|c: char| c.is_ascii())
+                {
+                    text.push(' ');
+                }
+                text.push_str(part);
+                *len += chars;
+            }
+            _ => groups.push((part.to_string(), chars)),
+        }
+    }
+
+    let mut result = Vec::with_capacity(groups.len());
+    let mut cut = start;
+    let mut consumed = 0;
+    let count = groups.len();
+    for (i, (text, chars)) in groups.into_iter().enumerate() {
+        consumed += chars;
+        let mut next_cut = if i + 1 == count {
+            end
+        } else {
+            start + share(consumed)
+        };
+        if i + 1 != count {
+            if let Some(s) =
+                silences.and_then(|s| silence::nearest(s, next_cut, SPLIT_SILENCE_WINDOW))
+            {
+                if s.middle() > cut && s.middle() < end {
+                    next_cut = s.middle();
+                }
+            }
+        }
+        result.push(Subtitle::new(
+            sub.num,
+            ms_to_timestamp(cut),
+            ms_to_timestamp(next_cut),
+            text,
+        ));
+        cut = next_cut;
+    }
+    result
+}
+
+/// How much of a pause (in ms) is kept around a snapped boundary.
+const SNAP_PADDING: u32 = 150;
+
+/// Moves the start of the clip back to the end of the closest pause before it, and its
+/// end on to the start of the closest pause after it, keeping a little padding. Clips
+/// only grow, so no word is cut.
+fn snap_to_silence(sub: &mut Subtitle, silences: &[Silence], window: u32) {
+    let mut start = timestamp_to_ms(&sub.start_time);
+    let mut end = timestamp_to_ms(&sub.end_time);
+    if let Some(s) = silence::between(silences, start.saturating_sub(window), start)
+        .iter()
+        .rev()
+        .find(|s| s.end <= start)
+    {
+        start = s.end - SNAP_PADDING.min(s.end - s.start);
+    }
+    if let Some(s) = silence::between(silences, end, end + window)
+        .iter()
+        .find(|s| s.start >= end)
+    {
+        end = s.start + SNAP_PADDING.min(s.end - s.start);
+    }
+    if start < end {
+        sub.start_time = ms_to_timestamp(start);
+        sub.end_time = ms_to_timestamp(end);
+    }
+}
+
+/// How long before the end of the clip (in ms) a trailing pause may end, for speech of
+/// the next line that starts before its subtitle does.
+const TRIM_TOLERANCE: u32 = 1000;
+
+/// Ends the clip at the start of the last pause after the line's own end, when that pause
+/// lasts until the end of the clip or nearly, and starts after the clip does.
+fn trim_trailing_silence(sub: &mut Subtitle, own_end: Timestamp, silences: &[Silence]) {
+    let start = timestamp_to_ms(&sub.start_time);
+    let end = timestamp_to_ms(&sub.end_time);
+    let own_end = timestamp_to_ms(&own_end).max(start).min(end);
+    if let Some(s) = silence::between(silences, own_end, end)
+        .iter()
+        .rev()
+        .find(|s| s.end + TRIM_TOLERANCE >= end)
+    {
+        let trimmed = s.start + SNAP_PADDING.min(s.end - s.start);
+        if s.start > start && trimmed < end {
+            sub.end_time = ms_to_timestamp(trimmed);
+        }
+    }
+}
+
+/// Numbers the lines from 1 in order, `num - 1` being the index of their clip.
+pub fn renumber(subs: &mut [Subtitle]) {
+    for (i, sub) in subs.iter_mut().enumerate() {
+        sub.num = i + 1;
+    }
+}
+
+/// Parses the subtitles and computes the final time range of every clip.
+pub fn prepare(args: &MyArgs, thread_tx: &Sender<String>) -> Lines {
+    let mut subs = Subtitles::parse_from_file(&args.subtitle, Some("utf8"))
+        .unwrap()
+        .to_vec();
+
+    subs.sort();
+    let originals = subs.clone();
+    if let Some(correction) = args.correction {
+        for sub in subs.iter_mut() {
+            sub.start_time = ms_to_timestamp(correction.apply(timestamp_to_ms(&sub.start_time)));
+            sub.end_time = ms_to_timestamp(correction.apply(timestamp_to_ms(&sub.end_time)));
+        }
+    }
+
+    // Offsets are applied in both directions, keeping every time inside the audio.
+    let duration = probe::duration(&args.audiobook);
+    let shift = |t: &Timestamp| {
+        let ms = (i64::from(timestamp_to_ms(t)) + i64::from(args.start_offset)).max(0);
+        ms_to_timestamp(duration.map_or(ms, |d| ms.min(i64::from(d))) as u32)
+    };
+
+    // Collect all subtitle text into a string.
+    let mut subs_strings: Vec<String> = Vec::with_capacity(15000);
+    let mut subs2: Vec<Subtitle> = Vec::with_capacity(20000);
+    // End of each line before it is extended towards the next one.
+    let mut own_ends: Vec<Timestamp> = Vec::with_capacity(subs.len());
+    // Until renumbering, `num` is the index of the line in `originals`.
+    subs.iter()
+        .tuple_windows()
+        .enumerate()
+        .for_each(|(i, (n, np1))| {
+            let mut n2 = n.clone();
+            n2.num = i;
+            n2.start_time = shift(&n.start_time);
+            let mut end = np1.start_time;
+            if let Some(max_tail) = args.max_tail {
+                let cap = ms_to_timestamp(timestamp_to_ms(&n.end_time) + max_tail);
+                if cap < end {
+                    end = cap;
+                }
+            }
+            n2.end_time = shift(&end);
+            own_ends.push(shift(&n.end_time).min(n2.end_time));
+            subs2.push(n2);
+            subs_strings.push(n.text.to_owned());
+        });
+
+    let mut last = subs.last().unwrap().clone();
+    last.num = subs.len() - 1;
+    last.start_time = shift(&last.start_time);
+    last.end_time = shift(&last.end_time);
+    own_ends.push(last.end_time);
+    subs2.push(last);
+    subs_strings.push(subs.last().unwrap().text.to_owned());
+
+    let silences = if args.split_on_silence || args.snap_window.is_some() || args.trim_silence {
+        thread_tx
+            .send(String::from("Detecting silences...\n"))
+            .unwrap();
+        Some(silence::detect(&args.audiobook, -35.0, 250))
+    } else {
+        None
+    };
+
+    if let (Some(window), Some(silences)) = (args.snap_window, &silences) {
+        for sub in subs2.iter_mut() {
+            snap_to_silence(sub, silences, window);
+        }
+    }
+
+    if let (true, Some(silences)) = (args.trim_silence, &silences) {
+        for sub in subs2.iter_mut() {
+            trim_trailing_silence(sub, own_ends[sub.num], silences);
+        }
+    }
+
+    if let Some(max_length) = args.max_length {
+        let silences = silences.as_deref().filter(|_| args.split_on_silence);
+        let before = subs2.len();
+        // Cuts are placed in the line's own span, only the last part gets the extension.
+        subs2 = subs2
+            .iter()
+            .flat_map(|s| {
+                let mut own = s.clone();
+                own.end_time = own_ends[s.num].min(s.end_time);
+                let mut parts = split_long(&own, max_length, silences);
+                if let Some(last) = parts.last_mut() {
+                    last.end_time = s.end_time;
+                }
+                parts
+            })
+            .collect();
+        thread_tx
+            .send(format!(
+                "Split long lines: {} lines became {}\n",
+                before,
+                subs2.len()
+            ))
+            .unwrap();
+    }
+
+    let (mut subs2, removed) = filter::apply(&args.filters, subs2, thread_tx);
+    for (rule, count) in removed {
+        thread_tx
+            .send(format!("Filter: removed {count} lines {rule}\n"))
+            .unwrap();
+    }
+
+    let originals = subs2.iter().map(|s| originals[s.num].clone()).collect();
+    renumber(&mut subs2);
+    let invalid = subs2.iter().filter(|s| s.start_time >= s.end_time).count();
+    if invalid > 0 {
+        thread_tx
+            .send(format!(
+                "Warning: {invalid} lines have an empty or inverted time range after offsets, they will be silent.\n"
+            ))
+            .unwrap();
+    }
+    Lines {
+        clips: subs2,
+        originals,
+    }
+}
+
+pub fn process(args: MyArgs, subs: &[Subtitle], thread_tx: Sender<String>) {
     // let mut rubies = None;
 
     // let gch = Getch::new();
@@ -149,39 +458,14 @@ pub fn process(args: MyArgs, thread_tx: Sender<String>) {
     //     }
     // });
 
-    let mut subs = Subtitles::parse_from_file(&args.subtitle, Some("utf8"))
-        .unwrap()
-        .to_vec();
-
-    subs.sort();
-    let mintime = Timestamp::new(0, 0, 0, args.start_offset.unsigned_abs() as u16);
-
     let path = format!("./gen/{}/", args.prefix);
 
     std::fs::create_dir_all(&path).unwrap();
 
-    // Collect all subtitle text into a string.
-    let mut subs_strings: Vec<String> = Vec::with_capacity(15000);
-    let mut subs2: Vec<Subtitle> = Vec::with_capacity(20000);
-    subs.iter().tuple_windows().for_each(|(n, np1)| {
-        let mut n2 = n.clone();
-        if n.start_time > mintime {
-            n2.start_time.add_milliseconds(args.start_offset);
-        }
-        n2.end_time = np1.start_time;
-        n2.end_time.add_milliseconds(args.start_offset);
-        subs2.push(n2);
-        subs_strings.push(n.text.to_owned());
-    });
-
-    subs2.push(subs.last().unwrap().clone());
-    subs_strings.push(subs.last().unwrap().text.to_owned());
-
-
     let n = AtomicUsize::new(0);
     let m = subs.len();
     // TODO benchmark, audiobook2srs don't care about order
-    subs2.chunks(CHUNK_SIZE)
+    subs.chunks(CHUNK_SIZE)
         .enumerate()
         .par_bridge()
         // .par_chunks()
@@ -217,3 +501,129 @@ pub fn process(args: MyArgs, thread_tx: Sender<String>) {
             thread_tx.send(format!("{n:?}/{m} completed!\n")).unwrap();
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(subs: &[Subtitle]) -> Vec<(u32, u32, &str)> {
+        subs.iter()
+            .map(|s| {
+                (
+                    timestamp_to_ms(&s.start_time),
+                    timestamp_to_ms(&s.end_time),
+                    s.text.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_text_keeps_punctuation_and_closers() {
+        assert_eq!(
+            split_text("「はい。」そう！ですか", SENTENCE_ENDS),
+            vec!["「はい。」", "そう！", "ですか"]
+        );
+        assert_eq!(split_text("One. Two.", SENTENCE_ENDS), vec!["One.", "Two."]);
+        assert_eq!(split_text("no end", SENTENCE_ENDS), vec!["no end"]);
+    }
+
+    #[test]
+    fn short_lines_are_not_split() {
+        let sub = test_line(7, 0, 4000, "あああ。いいい。");
+        assert_eq!(split_long(&sub, 5000, None), vec![sub]);
+    }
+
+    #[test]
+    fn split_long_cuts_proportionally() {
+        let parts = split_long(
+            &test_line(7, 0, 10000, "あああ。いいいいいい。"),
+            5000,
+            None,
+        );
+        assert_eq!(
+            spans(&parts),
+            vec![(0, 3636, "あああ。"), (3636, 10000, "いいいいいい。")]
+        );
+        assert!(parts.iter().all(|p| p.num == 7));
+    }
+
+    #[test]
+    fn split_long_groups_parts_that_fit() {
+        let parts = split_long(&test_line(7, 1000, 15000, "One. Two. Three."), 9000, None);
+        assert_eq!(
+            spans(&parts),
+            vec![(1000, 9000, "One. Two."), (9000, 15000, "Three.")]
+        );
+    }
+
+    #[test]
+    fn snapping_only_widens_the_clip() {
+        let silences = [
+            Silence {
+                start: 500,
+                end: 900,
+            },
+            Silence {
+                start: 1050,
+                end: 1300,
+            },
+            Silence {
+                start: 4500,
+                end: 4800,
+            },
+            Silence {
+                start: 5300,
+                end: 5600,
+            },
+        ];
+        let mut sub = test_line(1, 1000, 5000, "");
+        snap_to_silence(&mut sub, &silences, 1000);
+        assert_eq!(spans(&[sub]), vec![(750, 5450, "")]);
+    }
+
+    #[test]
+    fn trims_a_gap_that_ends_before_the_next_line() {
+        let silences = [
+            Silence {
+                start: 1000,
+                end: 1500,
+            },
+            Silence {
+                start: 2500,
+                end: 11500,
+            },
+        ];
+        let mut sub = test_line(1, 0, 12000, "");
+        trim_trailing_silence(&mut sub, ms_to_timestamp(2000), &silences);
+        assert_eq!(spans(&[sub]), vec![(0, 2650, "")]);
+    }
+
+    #[test]
+    fn keeps_speech_after_an_early_pause() {
+        let silences = [Silence {
+            start: 2500,
+            end: 3000,
+        }];
+        let mut sub = test_line(1, 0, 12000, "");
+        trim_trailing_silence(&mut sub, ms_to_timestamp(2000), &silences);
+        assert_eq!(spans(&[sub]), vec![(0, 12000, "")]);
+    }
+
+    #[test]
+    fn split_long_moves_cuts_to_pauses() {
+        let silences = [Silence {
+            start: 4000,
+            end: 4400,
+        }];
+        let parts = split_long(
+            &test_line(7, 0, 10000, "あああ。いいいいいい。"),
+            5000,
+            Some(&silences),
+        );
+        assert_eq!(
+            spans(&parts),
+            vec![(0, 4200, "あああ。"), (4200, 10000, "いいいいいい。")]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+};
+
+use srtlib::Subtitle;
+
+/// Hashes of the sentences of every deck generated before, one per line in hex.
+const HISTORY: &str = "./audiobook2srs-history.txt";
+/// Kanji written as digits, counted as part of a number.
+const KANJI_DIGITS: &[char] = &[
+    '〇', '一', '二', '三', '四', '五', '六', '七', '八', '九', '十', '百', '千',
+];
+
+/// What is done with a sentence already seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    /// No card for it.
+    Skip,
+    /// Its clip is added to the first card with the same sentence.
+    Merge,
+    /// Its card is kept, tagged.
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplicate {
+    /// Of the given earlier line of this deck.
+    Of(usize),
+    /// Of a sentence from a previous deck.
+    Seen,
+}
+
+/// Hash of the sentence without punctuation, spacing, case or the value of its
+/// numbers, so that "第1章", "第12章" and "第十二章" count as the same. None when
+/// nothing is left. FNV-1a, which unlike the standard hasher stays the same across builds.
+pub fn hash(text: &str) -> Option<u64> {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut empty = true;
+    let mut in_number = false;
+    for c in text.chars().filter(|c| c.is_alphanumeric()) {
+        let digit = (c.is_numeric() && !c.is_alphabetic()) || KANJI_DIGITS.contains(&c);
+        if digit && in_number {
+            continue;
+        }
+        in_number = digit;
+        let c = if digit {
+            '0'
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        };
+        let mut buf = [0; 4];
+        for byte in c.encode_utf8(&mut buf).bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        empty = false;
+    }
+    (!empty).then_some(hash)
+}
+
+pub fn load_history() -> HashSet<u64> {
+    fs::read_to_string(HISTORY)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| u64::from_str_radix(line.trim(), 16).ok())
+        .collect()
+}
+
+pub fn save_history(hashes: impl IntoIterator<Item = u64>) -> std::io::Result<()> {
+    let seen = load_history();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(HISTORY)?;
+    for hash in hashes.into_iter().filter(|h| !seen.contains(h)) {
+        writeln!(file, "{:016x}", hash)?;
+    }
+    Ok(())
+}
+
+/// For each line, what it repeats, if anything. Earlier lines of the deck are checked
+/// before the history.
+pub fn find(subs: &[Subtitle], history: &HashSet<u64>) -> Vec<Option<Duplicate>> {
+    let mut first: HashMap<u64, usize> = HashMap::new();
+    subs.iter()
+        .enumerate()
+        .map(|(i, sub)| {
+            let hash = hash(&sub.text)?;
+            if let Some(&j) = first.get(&hash) {
+                return Some(Duplicate::Of(j));
+            }
+            first.insert(hash, i);
+            history.contains(&hash).then_some(Duplicate::Seen)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_ignores_the_value_of_numbers() {
+        assert_eq!(hash("第1章"), hash("第12章"));
+        assert_eq!(hash("第1章"), hash("第十二章"));
+        assert_eq!(hash("Chapter 3."), hash("chapter 300"));
+        assert_ne!(hash("第1章"), hash("第1節"));
+        assert_eq!(hash("、。"), None);
+    }
+}
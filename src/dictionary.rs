@@ -0,0 +1,186 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::html;
+
+/// An entry as it goes on a card, every field as escaped HTML.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub word: String,
+    pub reading: String,
+    /// Numbered senses, one per line.
+    pub definition: String,
+}
+
+/// Entries of a local dictionary, by written form and by reading.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    entries: HashMap<String, Vec<Entry>>,
+}
+
+fn number_senses(senses: &[String]) -> String {
+    match senses {
+        [sense] => sense.clone(),
+        _ => senses
+            .iter()
+            .enumerate()
+            .map(|(i, sense)| format!("{}. {}", i + 1, sense))
+            .collect::<Vec<String>>()
+            .join("<br>"),
+    }
+}
+
+/// XML text as it reads, with the predefined entities replaced.
+fn xml_text(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Every piece of text in a Yomitan glossary item, whether a plain string, a text
+/// object or structured content.
+fn glossary_text(item: &Value, out: &mut Vec<String>) {
+    match item {
+        Value::String(text) => out.push(text.clone()),
+        Value::Array(items) => items.iter().for_each(|i| glossary_text(i, out)),
+        Value::Object(object) => {
+            if let Some(text) = object.get("text") {
+                glossary_text(text, out);
+            }
+            if let Some(content) = object.get("content") {
+                glossary_text(content, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Dictionary {
+    /// Loads a JMdict XML file or a Yomitan dictionary zip, keeping only the entries
+    /// written or read as one of the `wanted` words.
+    pub fn load(path: &Path, wanted: &HashSet<String>) -> Result<Dictionary, String> {
+        let mut dictionary = Dictionary::default();
+        let is_zip = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+        if is_zip {
+            dictionary.load_yomitan(path, wanted)?;
+        } else {
+            dictionary.load_jmdict(path, wanted)?;
+        }
+        Ok(dictionary)
+    }
+
+    fn add(&mut self, entry: Entry, forms: &[String], wanted: &HashSet<String>) {
+        for form in forms.iter().filter(|f| wanted.contains(*f)) {
+            self.entries
+                .entry(form.clone())
+                .or_default()
+                .push(entry.clone());
+        }
+    }
+
+    /// JMdict keeps one element per line, which is all this reads.
+    fn load_jmdict(&mut self, path: &Path, wanted: &HashSet<String>) -> Result<(), String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let element = Regex::new(r"<(keb|reb|gloss)([^>]*)>([^<]*)</").unwrap();
+        let (mut kebs, mut rebs, mut senses): (Vec<String>, Vec<String>, Vec<String>) =
+            Default::default();
+        let mut glosses: Vec<String> = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line == "</sense>" && !glosses.is_empty() {
+                senses.push(glosses.join("; "));
+                glosses.clear();
+            } else if line == "</entry>" {
+                if !senses.is_empty() && (!kebs.is_empty() || !rebs.is_empty()) {
+                    let entry = Entry {
+                        word: html::escape(
+                            kebs.first().or(rebs.first()).map_or("", String::as_str),
+                        ),
+                        reading: html::escape(rebs.first().map_or("", String::as_str)),
+                        definition: number_senses(&senses),
+                    };
+                    let forms: Vec<String> = kebs.iter().chain(&rebs).cloned().collect();
+                    self.add(entry, &forms, wanted);
+                }
+                kebs.clear();
+                rebs.clear();
+                senses.clear();
+            } else if let Some(captures) = element.captures(line) {
+                let text = xml_text(&captures[3]);
+                match &captures[1] {
+                    "keb" => kebs.push(text),
+                    "reb" => rebs.push(text),
+                    // The full JMdict has glosses in several languages, English ones untagged.
+                    _ if captures[2].contains("xml:lang") && !captures[2].contains("\"eng\"") => {}
+                    _ => glosses.push(html::escape(&text)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_yomitan(&mut self, path: &Path, wanted: &HashSet<String>) -> Result<(), String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+        for i in 0..archive.len() {
+            let mut bank = archive.by_index(i).map_err(|e| e.to_string())?;
+            if !bank.name().starts_with("term_bank_") {
+                continue;
+            }
+            let mut contents = String::new();
+            bank.read_to_string(&mut contents)
+                .map_err(|e| e.to_string())?;
+            let terms: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+            // [expression, reading, definition tags, rules, score, glossary, sequence, term tags]
+            for term in terms.as_array().into_iter().flatten() {
+                let word = term[0].as_str().unwrap_or_default().to_string();
+                let reading = term[1].as_str().unwrap_or_default().to_string();
+                let mut senses = Vec::new();
+                for item in term[5].as_array().into_iter().flatten() {
+                    let mut texts = Vec::new();
+                    glossary_text(item, &mut texts);
+                    senses.push(html::escape(&texts.join(" ")));
+                }
+                if word.is_empty() || senses.is_empty() {
+                    continue;
+                }
+                let forms = [word.clone(), reading.clone()];
+                let reading = if reading.is_empty() {
+                    word.clone()
+                } else {
+                    reading
+                };
+                self.add(
+                    Entry {
+                        word: html::escape(&word),
+                        reading: html::escape(&reading),
+                        definition: number_senses(&senses),
+                    },
+                    &forms,
+                    wanted,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The first entry written or read as `word`.
+    pub fn lookup(&self, word: &str) -> Option<&Entry> {
+        self.entries.get(word).and_then(|entries| entries.first())
+    }
+}
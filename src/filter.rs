@@ -0,0 +1,113 @@
+use std::sync::mpsc::Sender;
+
+use regex::Regex;
+use srtlib::Subtitle;
+
+use crate::{converter::timestamp_to_ms, furigana::Analyser, html, known::KnownWords};
+
+/// Rules dropping lines that should not become cards. Unset rules keep everything.
+#[derive(Debug, Clone, Default)]
+pub struct FilterRules {
+    pub pattern: Option<Regex>,
+    pub min_chars: usize,
+    /// In ms.
+    pub min_duration: Option<u32>,
+    /// In ms.
+    pub max_duration: Option<u32>,
+    pub non_speech: bool,
+    pub known_words: Option<KnownWords>,
+}
+
+/// True when the line holds nothing but punctuation, symbols, or bracketed markers such
+/// as (笑), [music] or ♪...♪.
+fn is_non_speech(markers: &Regex, text: &str) -> bool {
+    !markers
+        .replace_all(text, "")
+        .chars()
+        .any(char::is_alphanumeric)
+}
+
+/// Applies the rules in order, returning the kept lines and how many each rule removed.
+pub fn apply(
+    rules: &FilterRules,
+    subs: Vec<Subtitle>,
+    thread_tx: &Sender<String>,
+) -> (Vec<Subtitle>, Vec<(String, usize)>) {
+    let duration =
+        |s: &Subtitle| timestamp_to_ms(&s.end_time).saturating_sub(timestamp_to_ms(&s.start_time));
+    let markers = Regex::new(r"\([^)]*\)|（[^）]*）|\[[^\]]*\]|【[^】]*】|♪[^♪]*♪?").unwrap();
+    let analyser = rules
+        .known_words
+        .as_ref()
+        .and_then(|known| match Analyser::new() {
+            Ok(analyser) => Some((known, analyser)),
+            Err(err) => {
+                thread_tx
+                    .send(format!(
+                        "Could not load the morphological analyser, lines are not filtered by known words: {err}\n"
+                    ))
+                    .unwrap();
+                None
+            }
+        });
+    let mut checks: Vec<(String, Box<dyn Fn(&Subtitle) -> bool + '_>)> = Vec::new();
+    if let Some(pattern) = &rules.pattern {
+        checks.push((
+            format!("matching /{pattern}/"),
+            Box::new(move |s: &Subtitle| pattern.is_match(&s.text)),
+        ));
+    }
+    if rules.min_chars > 0 {
+        checks.push((
+            format!("shorter than {} characters", rules.min_chars),
+            Box::new(|s: &Subtitle| {
+                s.text.chars().filter(|c| !c.is_whitespace()).count() < rules.min_chars
+            }),
+        ));
+    }
+    if let Some(min) = rules.min_duration {
+        checks.push((
+            format!("shorter than {:.1}s", f64::from(min) / 1000.0),
+            Box::new(move |s: &Subtitle| duration(s) < min),
+        ));
+    }
+    if let Some(max) = rules.max_duration {
+        checks.push((
+            format!("longer than {:.1}s", f64::from(max) / 1000.0),
+            Box::new(move |s: &Subtitle| duration(s) > max),
+        ));
+    }
+    if rules.non_speech {
+        checks.push((
+            String::from("without speech"),
+            Box::new(|s: &Subtitle| is_non_speech(&markers, &s.text)),
+        ));
+    }
+
+    if let Some((known, analyser)) = &analyser {
+        checks.push((
+            format!("without exactly {} unknown words", known.unknown),
+            Box::new(move |s: &Subtitle| {
+                known.unknown_words(analyser, &html::plain(&s.text)).len() != known.unknown
+            }),
+        ));
+    }
+
+    let mut removed = vec![0; checks.len()];
+    let kept = subs
+        .into_iter()
+        .filter(|s| match checks.iter().position(|(_, check)| check(s)) {
+            Some(i) => {
+                removed[i] += 1;
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let report = checks
+        .into_iter()
+        .map(|(name, _)| name)
+        .zip(removed)
+        .collect();
+    (kept, report)
+}
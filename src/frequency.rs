@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use serde_json::Value;
+
+/// Upper ranks of the frequency tags, the rest being tagged rare.
+const BUCKETS: &[u32] = &[1000, 5000, 10000, 20000];
+
+/// Rank of each word in a frequency list, 1 being the most common.
+#[derive(Debug, Default)]
+pub struct Frequencies {
+    ranks: HashMap<String, u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub rarest: u32,
+    pub average: u32,
+    /// Words missing from the list.
+    pub unranked: usize,
+}
+
+impl Stats {
+    pub fn tag(&self) -> String {
+        match BUCKETS.iter().find(|&&b| self.rarest <= b) {
+            Some(bucket) => format!("freq::top{}k", bucket / 1000),
+            None => String::from("freq::rare"),
+        }
+    }
+}
+
+/// The rank in a Yomitan frequency entry, which is either a number, an object with a
+/// value, or either of those for a given reading.
+fn yomitan_rank(data: &Value) -> Option<u32> {
+    match data {
+        Value::Number(n) => n.as_u64().map(|n| n as u32),
+        Value::Object(object) => object
+            .get("frequency")
+            .and_then(yomitan_rank)
+            .or_else(|| object.get("value").and_then(yomitan_rank)),
+        _ => None,
+    }
+}
+
+impl Frequencies {
+    /// Loads a Yomitan frequency dictionary zip, or a text file with a word per line,
+    /// either most common first or followed by its rank after a tab or a comma.
+    pub fn load(path: &Path) -> Result<Frequencies, String> {
+        let mut frequencies = Frequencies::default();
+        let is_zip = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+        if is_zip {
+            frequencies.load_yomitan(path)?;
+        } else {
+            let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            for (i, line) in contents
+                .trim_start_matches('\u{feff}')
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .enumerate()
+            {
+                let mut columns = line.split(['\t', ',']);
+                let word = columns.next().unwrap_or_default().trim().to_lowercase();
+                let rank = columns
+                    .next()
+                    .and_then(|r| r.trim().parse().ok())
+                    .unwrap_or(i as u32 + 1);
+                frequencies.add(word, rank);
+            }
+        }
+        Ok(frequencies)
+    }
+
+    fn add(&mut self, word: String, rank: u32) {
+        let best = self.ranks.entry(word).or_insert(rank);
+        *best = (*best).min(rank);
+    }
+
+    fn load_yomitan(&mut self, path: &Path) -> Result<(), String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+        for i in 0..archive.len() {
+            let mut bank = archive.by_index(i).map_err(|e| e.to_string())?;
+            if !bank.name().starts_with("term_meta_bank_") {
+                continue;
+            }
+            let mut contents = String::new();
+            bank.read_to_string(&mut contents)
+                .map_err(|e| e.to_string())?;
+            let terms: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+            // [term, "freq", data]
+            for term in terms.as_array().into_iter().flatten() {
+                if term[1] != "freq" {
+                    continue;
+                }
+                if let (Some(word), Some(rank)) = (term[0].as_str(), yomitan_rank(&term[2])) {
+                    self.add(word.to_lowercase(), rank);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranks.len()
+    }
+
+    /// Ranks of the words, those missing from the list counting as the rarest.
+    pub fn stats(&self, words: &[String]) -> Stats {
+        if words.is_empty() {
+            return Stats::default();
+        }
+        let ranks: Vec<u32> = words
+            .iter()
+            .map(|w| {
+                self.ranks
+                    .get(w)
+                    .copied()
+                    .unwrap_or(self.ranks.len() as u32 + 1)
+            })
+            .collect();
+        Stats {
+            unranked: words
+                .iter()
+                .filter(|w| !self.ranks.contains_key(*w))
+                .count(),
+            rarest: ranks.iter().copied().max().unwrap_or(0),
+            average: (ranks.iter().map(|&r| u64::from(r)).sum::<u64>() / ranks.len() as u64) as u32,
+        }
+    }
+}
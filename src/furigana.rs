@@ -0,0 +1,239 @@
+use lindera::{DictionaryConfig, DictionaryKind, Mode, Tokenizer, TokenizerConfig};
+use srtlib::Subtitle;
+
+/// Parts of speech that only hold the sentence together.
+const FUNCTION_WORDS: &[&str] = &["助詞", "助動詞", "記号", "フィラー"];
+
+use crate::book::Ruby;
+
+/// How many rubies may be skipped to find the next one heard, for readings of words
+/// the subtitles got wrong or left out.
+const LOOKAHEAD: usize = 30;
+/// Share of the line's characters that must also be in the ruby's paragraph.
+const MIN_OVERLAP: f64 = 0.5;
+
+/// Anki furigana syntax, `漢字[かんじ]`. The space marks where the base starts and is
+/// hidden by Anki.
+pub fn annotate(base: &str, reading: &str) -> String {
+    format!(" {}[{}]", base, reading)
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '々')
+}
+
+fn to_hiragana(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Furigana for one word, leaving out the kana it starts or ends with, so that
+/// `食べる` gives ` 食[た]べる`.
+fn annotate_word(surface: &str, reading: &str) -> String {
+    let surface: Vec<char> = surface.chars().collect();
+    let reading: Vec<char> = reading.chars().collect();
+    let same = |a: &char, b: &char| to_hiragana(&a.to_string()) == b.to_string();
+    let prefix = surface
+        .iter()
+        .zip(&reading)
+        .take_while(|(a, b)| !is_kanji(**a) && same(a, b))
+        .count();
+    let suffix = surface[prefix..]
+        .iter()
+        .rev()
+        .zip(reading[prefix..].iter().rev())
+        .take_while(|(a, b)| !is_kanji(**a) && same(a, b))
+        .count();
+    let base: String = surface[prefix..surface.len() - suffix].iter().collect();
+    let kana: String = reading[prefix..reading.len() - suffix].iter().collect();
+    if base.is_empty() || kana.is_empty() {
+        return surface.iter().collect();
+    }
+    format!(
+        "{}{}{}",
+        surface[..prefix].iter().collect::<String>(),
+        annotate(&base, &kana),
+        surface[surface.len() - suffix..].iter().collect::<String>()
+    )
+}
+
+/// Offline morphological analysis with the IPADIC dictionary embedded in the binary.
+pub struct Analyser {
+    tokenizer: Tokenizer,
+}
+
+impl Analyser {
+    pub fn new() -> Result<Analyser, String> {
+        let config = TokenizerConfig {
+            dictionary: DictionaryConfig {
+                kind: Some(DictionaryKind::IPADIC),
+                path: None,
+            },
+            user_dictionary: None,
+            mode: Mode::Normal,
+        };
+        let tokenizer = Tokenizer::from_config(config).map_err(|e| e.to_string())?;
+        Ok(Analyser { tokenizer })
+    }
+
+    /// The text with furigana on its kanji words, and the text read out in hiragana.
+    /// Words the dictionary does not know are left as they are in both.
+    pub fn readings(&self, text: &str) -> (String, String) {
+        let Ok(mut tokens) = self.tokenizer.tokenize(text) else {
+            return (text.to_string(), text.to_string());
+        };
+        let mut furigana = String::new();
+        let mut reading = String::new();
+        for token in tokens.iter_mut() {
+            let surface = token.text.to_string();
+            // IPADIC details: part of speech (4), conjugation (2), base form, reading, pronunciation.
+            let kana = token
+                .get_details()
+                .and_then(|details| details.get(7).map(|r| r.to_string()))
+                .filter(|r| r != "*")
+                .map(|r| to_hiragana(&r));
+            match kana {
+                Some(kana) => {
+                    if surface.chars().any(is_kanji) {
+                        furigana.push_str(&annotate_word(&surface, &kana));
+                    } else {
+                        furigana.push_str(&surface);
+                    }
+                    reading.push_str(&kana);
+                }
+                None => {
+                    furigana.push_str(&surface);
+                    reading.push_str(&to_hiragana(&surface));
+                }
+            }
+        }
+        (furigana, reading)
+    }
+
+    /// Dictionary forms of the content words of the text, in order, lowercased.
+    /// Words the dictionary does not know are kept as they are written.
+    pub fn words(&self, text: &str) -> Vec<String> {
+        let Ok(mut tokens) = self.tokenizer.tokenize(text) else {
+            return Vec::new();
+        };
+        tokens
+            .iter_mut()
+            .filter_map(|token| {
+                let surface = token.text.to_string();
+                if !surface.chars().any(char::is_alphanumeric) {
+                    return None;
+                }
+                let details = token.get_details().unwrap_or_default();
+                if details
+                    .first()
+                    .is_some_and(|pos| FUNCTION_WORDS.contains(pos))
+                {
+                    return None;
+                }
+                let base = details
+                    .get(6)
+                    .filter(|b| **b != "*")
+                    .map_or(surface, |b| b.to_string());
+                Some(base.to_lowercase())
+            })
+            .collect()
+    }
+}
+
+fn matches(ruby: &Ruby, text: &str) -> bool {
+    text.contains(&ruby.base) && textdistance::str::overlap(&ruby.context, text) > MIN_OVERLAP
+}
+
+/// Each line with the readings of the book's rubies added in Anki format.
+/// Rubies are used in reading order: a line takes the first one it matches among the
+/// next few, then every following one it keeps matching, each after the previous.
+pub fn from_rubies(lines: &[Subtitle], rubies: &[Ruby]) -> Vec<String> {
+    let mut next = 0;
+    lines
+        .iter()
+        .map(|line| {
+            let mut text = String::new();
+            let mut rest = line.text.as_str();
+            if let Some(skip) = rubies[next..]
+                .iter()
+                .take(LOOKAHEAD)
+                .position(|r| matches(r, &line.text))
+            {
+                next += skip;
+                while let Some(ruby) = rubies.get(next) {
+                    let Some(at) = rest.find(&ruby.base) else {
+                        break;
+                    };
+                    if !matches(ruby, &line.text) {
+                        break;
+                    }
+                    text.push_str(&rest[..at]);
+                    text.push_str(&annotate(&ruby.base, &ruby.reading));
+                    rest = &rest[at + ruby.base.len()..];
+                    next += 1;
+                }
+            }
+            text.push_str(rest);
+            text
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::test_line;
+
+    fn ruby(base: &str, reading: &str, context: &str) -> Ruby {
+        Ruby {
+            base: base.to_string(),
+            reading: reading.to_string(),
+            context: context.to_string(),
+        }
+    }
+
+    #[test]
+    fn annotate_word_leaves_out_kana() {
+        assert_eq!(annotate_word("食べる", "たべる"), " 食[た]べる");
+        assert_eq!(annotate_word("お茶", "おちゃ"), "お 茶[ちゃ]");
+        assert_eq!(annotate_word("東京", "とうきょう"), " 東京[とうきょう]");
+        assert_eq!(annotate_word("ケーキ", "けーき"), "ケーキ");
+    }
+
+    #[test]
+    fn to_hiragana_converts_katakana_only() {
+        assert_eq!(to_hiragana("カタカナとABC"), "かたかなとABC");
+    }
+
+    #[test]
+    fn from_rubies_annotates_in_order() {
+        let rubies = [
+            ruby("漢字", "かんじ", "漢字を読む。"),
+            ruby("読", "よ", "漢字を読む。"),
+        ];
+        assert_eq!(
+            from_rubies(&[test_line(1, 0, 1000, "漢字を読む。")], &rubies),
+            vec![" 漢字[かんじ]を 読[よ]む。"]
+        );
+    }
+
+    #[test]
+    fn from_rubies_skips_other_paragraphs() {
+        let rubies = [
+            ruby("漢字", "かんじ", "まったく別の段落の漢字"),
+            ruby("漢字", "かんじ", "漢字を読む。"),
+        ];
+        let lines = [
+            test_line(1, 0, 1000, "何もない"),
+            test_line(2, 1000, 2000, "漢字を読む。"),
+        ];
+        assert_eq!(
+            from_rubies(&lines, &rubies),
+            vec!["何もない", " 漢字[かんじ]を読む。"]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+static TAGS: OnceLock<Regex> = OnceLock::new();
+static COLOR: OnceLock<Regex> = OnceLock::new();
+static VALID_COLOR: OnceLock<Regex> = OnceLock::new();
+
+/// The srt formatting tags and ASS override codes.
+fn tags() -> &'static Regex {
+    TAGS.get_or_init(|| Regex::new(r#"(?i)<(/?)(i|b|u|font)\b([^>]*)>|\{\\[^}]*\}"#).unwrap())
+}
+
+/// Text with the characters that mean something in HTML escaped.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Subtitle text made safe for an Anki field: HTML is escaped, line breaks become
+/// `<br>`, ASS override codes such as `{\an8}` are dropped, and the srt `<i>`, `<b>`,
+/// `<u>` and `<font color>` tags are kept as balanced HTML, or dropped as well when
+/// `keep_formatting` is false.
+pub fn to_html(text: &str, keep_formatting: bool) -> String {
+    let color = COLOR.get_or_init(|| Regex::new(r#"(?i)color\s*=\s*["']?([^"'\s>]+)"#).unwrap());
+    // Colours accepted from `<font color=...>`, as names or hex codes.
+    let valid_color = VALID_COLOR
+        .get_or_init(|| Regex::new(r"^(#[0-9a-fA-F]{3}|#[0-9a-fA-F]{6}|[a-zA-Z]+)$").unwrap());
+    let mut html = String::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut last = 0;
+    for tag in tags().captures_iter(text) {
+        let whole = tag.get(0).unwrap();
+        html.push_str(&escape(&text[last..whole.start()]));
+        last = whole.end();
+        let Some(name) = tag.get(2).filter(|_| keep_formatting) else {
+            continue;
+        };
+        let name = match name.as_str().to_lowercase().as_str() {
+            "i" => "i",
+            "b" => "b",
+            "u" => "u",
+            _ => "span",
+        };
+        if &tag[1] == "/" {
+            if let Some(at) = open.iter().rposition(|&o| o == name) {
+                for inner in open.drain(at..).rev() {
+                    html.push_str(&format!("</{}>", inner));
+                }
+            }
+        } else if name == "span" {
+            match color
+                .captures(&tag[3])
+                .map(|c| c[1].to_string())
+                .filter(|c| valid_color.is_match(c))
+            {
+                Some(c) => html.push_str(&format!("<span style=\"color:{}\">", c)),
+                None => html.push_str("<span>"),
+            }
+            open.push(name);
+        } else {
+            html.push_str(&format!("<{}>", name));
+            open.push(name);
+        }
+    }
+    html.push_str(&escape(&text[last..]));
+    for name in open.into_iter().rev() {
+        html.push_str(&format!("</{}>", name));
+    }
+    html.trim()
+        .replace("\r\n", "\n")
+        .split('\n')
+        .map(str::trim)
+        .collect::<Vec<&str>>()
+        .join("<br>")
+}
+
+/// Subtitle text without its tags and override codes, as it is read, for analysis.
+pub fn plain(text: &str) -> String {
+    tags().replace_all(text, "").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(
+            to_html("a < b & \"c\" > d", true),
+            "a &lt; b &amp; &quot;c&quot; &gt; d"
+        );
+        assert_eq!(
+            to_html("<script>x</script>", true),
+            "&lt;script&gt;x&lt;/script&gt;"
+        );
+    }
+
+    #[test]
+    fn balances_tags() {
+        assert_eq!(to_html("<i>a</b>", true), "<i>a</i>");
+        assert_eq!(to_html("<i><b>a</i>b</b>", true), "<i><b>a</b></i>b");
+        assert_eq!(to_html("a</u>", true), "a");
+    }
+
+    #[test]
+    fn keeps_valid_colours_only() {
+        assert_eq!(
+            to_html("<font color=\"#ff0000\">red</font>", true),
+            "<span style=\"color:#ff0000\">red</span>"
+        );
+        assert_eq!(
+            to_html("<font color=red;x>red</font>", true),
+            "<span>red</span>"
+        );
+    }
+
+    #[test]
+    fn drops_formatting_and_override_codes() {
+        assert_eq!(to_html("{\\an8}<i>Top</i>", false), "Top");
+        assert_eq!(to_html("{\\an8}<i>Top</i>", true), "<i>Top</i>");
+    }
+
+    #[test]
+    fn turns_line_breaks_into_br() {
+        assert_eq!(to_html(" one \r\n two\n", false), "one<br>two");
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
+/// How card images are resized and compressed before being packaged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageSettings {
+    /// Longest side in pixels, smaller images are left as they are.
+    pub max_dimension: u32,
+    /// 1 to 100, like most image editors.
+    pub quality: u8,
+    pub format: ImageFormat,
+}
+
+impl Default for ImageSettings {
+    /// Small enough to be synced to phones for every deck, still sharp on their screens.
+    fn default() -> Self {
+        Self {
+            max_dimension: 800,
+            quality: 80,
+            format: ImageFormat::Jpeg,
+        }
+    }
+}
+
+impl ImageSettings {
+    pub fn file_name(&self, stem: &str) -> String {
+        format!("{}.{}", stem, self.format.extension())
+    }
+
+    /// ffmpeg output arguments encoding a single frame into `output`, without metadata.
+    pub fn encode_args(&self, output: &str) -> Vec<String> {
+        let max = self.max_dimension;
+        let mut args = vec![
+            "-frames:v".to_string(),
+            "1".to_string(),
+            "-map_metadata".to_string(),
+            "-1".to_string(),
+            "-vf".to_string(),
+            format!("scale='min({max},iw)':'min({max},ih)':force_original_aspect_ratio=decrease"),
+        ];
+        let quality = u32::from(self.quality.clamp(1, 100));
+        match self.format {
+            // mjpeg goes from 2 (best) to 31 (worst).
+            ImageFormat::Jpeg => args.extend([
+                "-q:v".to_string(),
+                (2 + (100 - quality) * 29 / 100).to_string(),
+            ]),
+            ImageFormat::Webp => args.extend([
+                "-c:v".to_string(),
+                "libwebp".to_string(),
+                "-quality".to_string(),
+                quality.to_string(),
+            ]),
+        }
+        args.push(output.to_string());
+        args
+    }
+}
+
+/// Where the image of each note comes from.
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    None,
+    /// The same file on every note.
+    Cover(String),
+    /// A frame of the video grabbed at the middle of each line.
+    Frames(PathBuf),
+    /// The latest illustration of the EPUB (first path) reached in the audio (second path).
+    Epub(PathBuf, PathBuf),
+}
@@ -0,0 +1,48 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::furigana::Analyser;
+
+/// Words the learner already knows, to keep only the lines that teach something.
+#[derive(Debug, Clone, Default)]
+pub struct KnownWords {
+    words: HashSet<String>,
+    /// Lines are kept when they have exactly this many unknown words.
+    pub unknown: usize,
+}
+
+impl KnownWords {
+    /// Reads one word per line, from the given tab separated column (0 is the first),
+    /// so that both plain lists and notes exported from Anki as plain text work.
+    /// HTML and furigana readings left in Anki fields are removed.
+    pub fn load(path: &Path, column: usize, unknown: usize) -> Result<KnownWords, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let markup = Regex::new(r"<[^>]*>|\[[^\]]*\]|&nbsp;|\s").unwrap();
+        let words = contents
+            .trim_start_matches('\u{feff}')
+            .lines()
+            // Anki exports start with "#separator:tab" like headers.
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split('\t').nth(column))
+            .map(|word| markup.replace_all(word, "").to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        Ok(KnownWords { words, unknown })
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// The words of the text missing from the list, each once, in order.
+    pub fn unknown_words(&self, analyser: &Analyser, text: &str) -> Vec<String> {
+        analyser
+            .words(text)
+            .into_iter()
+            .filter(|word| !self.words.contains(word))
+            .unique()
+            .collect()
+    }
+}
@@ -0,0 +1,132 @@
+use relm4::{
+    factory::{DynamicIndex, FactoryComponent, FactorySender},
+    gtk::{
+        self,
+        prelude::{BoxExt, ButtonExt, CheckButtonExt, EditableExt, OrientableExt, WidgetExt},
+    },
+    RelmWidgetExt,
+};
+use srtlib::Subtitle;
+
+use crate::{converter, AppInMsg};
+
+/// One line of the preview list, with its final clip time range. Edits made in the row
+/// are kept here and used instead of the srt when generating the deck.
+#[derive(Debug)]
+pub struct LineRow {
+    pub sub: Subtitle,
+    /// The srt line the clip was made from, as it is in the file.
+    pub original: Subtitle,
+    pub include: bool,
+    /// How far the start and end were moved in the editor, in ms.
+    pub moved: (i64, i64),
+}
+
+#[derive(Debug)]
+pub enum LineRowMsg {
+    SetInclude(bool),
+    SetText(String),
+    SetStart(f64),
+    SetEnd(f64),
+}
+
+#[derive(Debug)]
+pub enum LineRowOutput {
+    Play(DynamicIndex),
+}
+
+fn seconds(ms: u32) -> f64 {
+    f64::from(ms) / 1000.0
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for LineRow {
+    type Init = (Subtitle, Subtitle);
+    type Input = LineRowMsg;
+    type Output = LineRowOutput;
+    type CommandOutput = ();
+    type ParentInput = AppInMsg;
+    type ParentWidget = gtk::ListBox;
+
+    view! {
+        gtk::Box {
+            set_orientation: gtk::Orientation::Horizontal,
+            set_spacing: 5,
+            set_margin_all: 2,
+
+            gtk::CheckButton {
+                set_active: self.include,
+                connect_toggled[sender] => move |btn| {
+                    sender.input(LineRowMsg::SetInclude(btn.is_active()));
+                }
+            },
+            gtk::Button::with_label("▶") {
+                connect_clicked[sender, index] => move |_| {
+                    sender.output(LineRowOutput::Play(index.clone()));
+                }
+            },
+            gtk::Label {
+                set_label: &self.sub.num.to_string(),
+            },
+            gtk::SpinButton::with_range(0.0, 360_000.0, 0.1) {
+                set_digits: 3,
+                set_value: seconds(converter::timestamp_to_ms(&self.sub.start_time)),
+                connect_value_changed[sender] => move |x| {
+                    sender.input(LineRowMsg::SetStart(x.value()));
+                }
+            },
+            gtk::SpinButton::with_range(0.0, 360_000.0, 0.1) {
+                set_digits: 3,
+                set_value: seconds(converter::timestamp_to_ms(&self.sub.end_time)),
+                connect_value_changed[sender] => move |x| {
+                    sender.input(LineRowMsg::SetEnd(x.value()));
+                }
+            },
+            gtk::Entry {
+                set_hexpand: true,
+                set_text: &self.sub.text,
+                connect_changed[sender] => move |entry| {
+                    sender.input(LineRowMsg::SetText(entry.text().to_string()));
+                }
+            },
+        }
+    }
+
+    fn init_model(
+        (sub, original): Self::Init,
+        _index: &DynamicIndex,
+        _sender: FactorySender<Self>,
+    ) -> Self {
+        Self {
+            sub,
+            original,
+            include: true,
+            moved: (0, 0),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Input, _sender: FactorySender<Self>) {
+        match msg {
+            LineRowMsg::SetInclude(include) => self.include = include,
+            LineRowMsg::SetText(text) => self.sub.text = text,
+            LineRowMsg::SetStart(val) => {
+                let ms = (val * 1000.0).round() as u32;
+                self.moved.0 +=
+                    i64::from(ms) - i64::from(converter::timestamp_to_ms(&self.sub.start_time));
+                self.sub.start_time = converter::ms_to_timestamp(ms)
+            }
+            LineRowMsg::SetEnd(val) => {
+                let ms = (val * 1000.0).round() as u32;
+                self.moved.1 +=
+                    i64::from(ms) - i64::from(converter::timestamp_to_ms(&self.sub.end_time));
+                self.sub.end_time = converter::ms_to_timestamp(ms)
+            }
+        }
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<AppInMsg> {
+        Some(match output {
+            LineRowOutput::Play(index) => AppInMsg::PlayLine(index),
+        })
+    }
+}
@@ -1,10 +1,16 @@
 use genanki_rs::{Deck, Field, Model, Note, Package, Template};
-use srtlib::Subtitles;
+use srtlib::{Subtitle, Subtitles};
 use std::{convert::identity, path::PathBuf};
-use worker::{AsyncHandler, AsyncHandlerInMsg};
+use worker::{AsyncHandler, AsyncHandlerInMsg, DeckOptions};
 
-use converter::MyArgs;
+use converter::{Lines, MyArgs};
+use dedup::DedupMode;
+use filter::FilterRules;
+use images::{ImageFormat, ImageSettings, ImageSource};
+use known::KnownWords;
+use lines::LineRow;
 use relm4::{
+    factory::{DynamicIndex, FactoryVecDeque},
     gtk::{
         self,
         prelude::{
@@ -20,8 +26,25 @@ use relm4_components::{
     open_button::{OpenButton, OpenButtonSettings},
     open_dialog::OpenDialogSettings,
 };
+use sync::Correction;
 
+mod align;
+mod book;
 mod converter;
+mod dedup;
+mod dictionary;
+mod filter;
+mod frequency;
+mod furigana;
+mod html;
+mod images;
+mod known;
+mod lines;
+mod probe;
+mod silence;
+mod sync;
+mod tags;
+mod translation;
 mod worker;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -29,6 +52,8 @@ enum ImageMode {
     Extract,
     None,
     Custom,
+    Frames,
+    Epub,
 }
 
 // #[derive(Debug)]
@@ -36,13 +61,58 @@ struct AppModel {
     open_srt: Controller<OpenButton>,
     srt_path: PathBuf,
     open_audio: Controller<OpenButton>,
+    open_image: Controller<OpenButton>,
+    image_path: PathBuf,
+    open_epub: Controller<OpenButton>,
+    epub_path: PathBuf,
+    book_furigana: bool,
+    book_text: bool,
+    generate_readings: bool,
+    open_translation: Controller<OpenButton>,
+    translation_path: PathBuf,
     audio_path: PathBuf,
     audio_ext: Option<AudioExt>,
+    audio_track: EntryBuffer,
     prefix: EntryBuffer,
     image: ImageMode,
+    has_cover: bool,
+    has_video: bool,
+    has_subtitles: bool,
+    subtitle_track: EntryBuffer,
+    cover: Option<String>,
+    image_settings: ImageSettings,
+    process_images: bool,
     buffer: gtk::TextBuffer,
     offset_before: f64,
     offset_after: f64,
+    max_length: f64,
+    split_on_silence: bool,
+    snap_window: f64,
+    max_tail: f64,
+    trim_silence: bool,
+    correction: Option<Correction>,
+    apply_correction: bool,
+    lines: FactoryVecDeque<LineRow>,
+    filter_pattern: EntryBuffer,
+    filter_min_chars: f64,
+    filter_min_duration: f64,
+    filter_max_duration: f64,
+    filter_non_speech: bool,
+    open_known: Controller<OpenButton>,
+    known_path: PathBuf,
+    known_column: f64,
+    known_unknown: f64,
+    known_words: Option<KnownWords>,
+    open_dictionary: Controller<OpenButton>,
+    dictionary_path: PathBuf,
+    open_frequency: Controller<OpenButton>,
+    frequency_path: PathBuf,
+    order_by_difficulty: bool,
+    dedup: Option<DedupMode>,
+    dedup_history: bool,
+    keep_formatting: bool,
+    tags: EntryBuffer,
+    metadata: bool,
     show_button: bool,
     worker: WorkerController<AsyncHandler>,
     sensitive: bool,
@@ -58,6 +128,12 @@ enum AudioExt {
 enum DialogOrigin {
     Audio,
     Srt,
+    Image,
+    Epub,
+    Translation,
+    KnownWords,
+    Dictionary,
+    Frequency,
 }
 
 #[derive(Debug)]
@@ -66,17 +142,56 @@ enum OffsetDirection {
     After,
 }
 
+#[derive(Debug)]
+enum FilterLimit {
+    MinChars,
+    MinDuration,
+    MaxDuration,
+    KnownColumn,
+    Unknown,
+}
+
 #[derive(Debug)]
 pub enum AppInMsg {
     UpdateBuffer(String, bool),
     SetImageMode(ImageMode),
+    SetProcessImages(bool),
+    SetBookFurigana(bool),
+    SetBookText(bool),
+    SetGenerateReadings(bool),
+    SetOrderByDifficulty(bool),
+    SetDedupMode(Option<DedupMode>),
+    SetDedupHistory(bool),
+    SetKeepFormatting(bool),
+    SetMetadata(bool),
+    UpdateImageSize(f64),
+    UpdateImageQuality(f64),
+    SetImageFormat(ImageFormat),
     Recheck,
     UpdateOffset(OffsetDirection, f64),
+    UpdateMaxLength(f64),
+    SetSplitOnSilence(bool),
+    UpdateSnapWindow(f64),
+    UpdateMaxTail(f64),
+    SetTrimSilence(bool),
+    AnalyseSync,
+    SyncProposal(Option<Correction>),
+    SetApplyCorrection(bool),
+    Preview,
+    PreviewReady(Lines),
+    PlayLine(DynamicIndex),
+    SaveLines,
+    UpdateFilterLimit(FilterLimit, f64),
+    SetFilterNonSpeech(bool),
+    DiscardLines,
     Start,
     Open(PathBuf, DialogOrigin),
+    ExtractSubtitles,
+    SubtitlesExtracted(Option<PathBuf>),
+    CoverReady(Option<String>),
     StartConversion,
     StartAudioSplit,
-    StartGenDeck,
+    StartGenDeck(Lines),
     Ended,
 }
 
@@ -85,6 +200,156 @@ enum AppOutMsg {
     Scroll,
 }
 
+impl AppModel {
+    fn prefix(&self) -> String {
+        self.prefix.text().to_string().replace(' ', "_")
+    }
+
+    fn args(&self) -> MyArgs {
+        MyArgs {
+            prefix: self.prefix(),
+            audiobook: self.audio_path.clone(),
+            subtitle: self.srt_path.clone(),
+            start_offset: (self.offset_before * 1000.0).round() as i32,
+            end_offset: (self.offset_after * 1000.0).round() as i32,
+            max_length: (self.max_length > 0.0).then_some((self.max_length * 1000.0) as u32),
+            split_on_silence: self.split_on_silence,
+            snap_window: (self.snap_window > 0.0).then_some(self.snap_window as u32),
+            max_tail: (self.max_tail > 0.0).then_some((self.max_tail * 1000.0) as u32),
+            trim_silence: self.trim_silence,
+            correction: self.correction.filter(|_| self.apply_correction),
+            lines: (!self.lines.is_empty()).then(|| self.edited_lines()),
+            filters: FilterRules {
+                pattern: Some(self.filter_pattern.text())
+                    .filter(|p| !p.is_empty())
+                    .and_then(|p| regex::Regex::new(&p).ok()),
+                min_chars: self.filter_min_chars as usize,
+                min_duration: (self.filter_min_duration > 0.0)
+                    .then_some((self.filter_min_duration * 1000.0) as u32),
+                max_duration: (self.filter_max_duration > 0.0)
+                    .then_some((self.filter_max_duration * 1000.0) as u32),
+                non_speech: self.filter_non_speech,
+                known_words: self.known_words.clone(),
+            },
+        }
+    }
+
+    fn deck_options(&self) -> DeckOptions {
+        DeckOptions {
+            translation: Some(self.translation_path.clone()).filter(|p| !p.as_os_str().is_empty()),
+            book: Some(self.epub_path.clone())
+                .filter(|p| (self.book_furigana || self.book_text) && !p.as_os_str().is_empty()),
+            book_furigana: self.book_furigana,
+            book_text: self.book_text,
+            generate_readings: self.generate_readings,
+            known_words: self.known_words.clone(),
+            dictionary: Some(self.dictionary_path.clone()).filter(|p| !p.as_os_str().is_empty()),
+            frequencies: Some(self.frequency_path.clone()).filter(|p| !p.as_os_str().is_empty()),
+            order_by_difficulty: self.order_by_difficulty,
+            dedup: self.dedup,
+            dedup_history: self.dedup.is_some() && self.dedup_history,
+            keep_formatting: self.keep_formatting,
+            audio: self.audio_path.clone(),
+            tags: tags::parse(&self.tags.text()),
+            metadata: self.metadata,
+        }
+    }
+
+    fn image_settings(&self) -> Option<ImageSettings> {
+        self.process_images.then_some(self.image_settings)
+    }
+
+    /// Index of the audio stream picked by the user, by position among the audio streams
+    /// or by language tag. None lets ffmpeg choose.
+    fn audio_stream(&self) -> Option<usize> {
+        let wanted = self.audio_track.text();
+        if wanted.trim().is_empty() {
+            return None;
+        }
+        probe::pick(
+            &probe::streams_of(&probe::streams(&self.audio_path), "audio"),
+            &wanted,
+        )
+        .map(|s| s.index)
+    }
+
+    fn load_known_words(&mut self, sender: &ComponentSender<Self>) {
+        if self.known_path.as_os_str().is_empty() {
+            return;
+        }
+        let column = (self.known_column as usize).saturating_sub(1);
+        let msg = match KnownWords::load(&self.known_path, column, self.known_unknown as usize) {
+            Ok(known) => {
+                let msg = format!("Loaded {} known words\n", known.len());
+                self.known_words = Some(known);
+                msg
+            }
+            Err(err) => {
+                self.known_words = None;
+                format!("Could not read the known words: {err}\n")
+            }
+        };
+        sender.input(AppInMsg::UpdateBuffer(msg, false));
+    }
+
+    fn can_start(&self) -> bool {
+        let pattern = self.filter_pattern.text();
+        self.prefix.length() > 0
+            && !self.audio_path.as_os_str().is_empty()
+            && !self.srt_path.as_os_str().is_empty()
+            && (pattern.is_empty() || regex::Regex::new(&pattern).is_ok())
+            && (self.image != ImageMode::Custom || !self.image_path.as_os_str().is_empty())
+            && (self.image != ImageMode::Extract || self.has_cover)
+            && (self.image != ImageMode::Frames || self.has_video)
+            && (self.image != ImageMode::Epub || !self.epub_path.as_os_str().is_empty())
+    }
+
+    /// The lines of the preview that are still ticked, with their edits.
+    fn edited_lines(&self) -> Lines {
+        let (mut clips, originals): (Vec<Subtitle>, Vec<Subtitle>) = self
+            .lines
+            .iter()
+            .filter(|l| l.include)
+            .map(|l| (l.sub.clone(), l.original.clone()))
+            .unzip();
+        converter::renumber(&mut clips);
+        Lines { clips, originals }
+    }
+
+    /// The ticked lines with the timing of the srt, so the saved file goes through the
+    /// offsets and padding only once when it is used again. Parts of a split line are
+    /// joined back, and time edits move the line by as much.
+    fn saved_lines(&self) -> Vec<Subtitle> {
+        let moved = |t: &srtlib::Timestamp, by: i64| {
+            converter::ms_to_timestamp((i64::from(converter::timestamp_to_ms(t)) + by).max(0) as u32)
+        };
+        let mut subs: Vec<Subtitle> = Vec::new();
+        let mut last: Option<&Subtitle> = None;
+        for row in self.lines.iter().filter(|l| l.include) {
+            match subs.last_mut() {
+                Some(sub) if last == Some(&row.original) => {
+                    if sub.text.ends_with(|c: char| c.is_ascii())
+                        && row.sub.text.starts_with(|c: char| c.is_ascii())
+                    {
+                        sub.text.push(' ');
+                    }
+                    sub.text.push_str(&row.sub.text);
+                    sub.end_time = moved(&row.original.end_time, row.moved.1);
+                }
+                _ => subs.push(Subtitle::new(
+                    0,
+                    moved(&row.original.start_time, row.moved.0),
+                    moved(&row.original.end_time, row.moved.1),
+                    row.sub.text.clone(),
+                )),
+            }
+            last = Some(&row.original);
+        }
+        converter::renumber(&mut subs);
+        subs
+    }
+}
+
 #[relm4::component]
 impl SimpleComponent for AppModel {
     type Input = AppInMsg;
@@ -125,7 +390,12 @@ impl SimpleComponent for AppModel {
         audio_filter.add_pattern("*.mp3");
         audio_filter.add_pattern("*.m4b");
         audio_filter.add_pattern("*.m4a");
-        audio_filter.set_name(Some("Audio files (.mp3, .m4b, .m4a)"));
+        audio_filter.add_pattern("*.mkv");
+        audio_filter.add_pattern("*.mp4");
+        audio_filter.add_pattern("*.webm");
+        audio_filter.set_name(Some(
+            "Audio and video files (.mp3, .m4b, .m4a, .mkv, .mp4, .webm)",
+        ));
 
         let open_audio = OpenButton::builder()
             .launch(OpenButtonSettings {
@@ -146,24 +416,211 @@ impl SimpleComponent for AppModel {
                 AppInMsg::Open(path, DialogOrigin::Audio)
             });
 
+        let image_filter = FileFilter::new();
+        image_filter.add_pattern("*.jpg");
+        image_filter.add_pattern("*.jpeg");
+        image_filter.add_pattern("*.png");
+        image_filter.add_pattern("*.webp");
+        image_filter.set_name(Some("Image files (.jpg, .png, .webp)"));
+
+        let open_image = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![image_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Image)
+            });
+
+        let epub_filter = FileFilter::new();
+        epub_filter.add_pattern("*.epub");
+        epub_filter.set_name(Some("Books (.epub)"));
+
+        let open_epub = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![epub_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Epub)
+            });
+
+        let translation_filter = FileFilter::new();
+        translation_filter.add_pattern("*.srt");
+        translation_filter.set_name(Some("Subtitle files (.srt)"));
+
+        let open_translation = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![translation_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Translation)
+            });
+
+        let known_filter = FileFilter::new();
+        known_filter.add_pattern("*.txt");
+        known_filter.add_pattern("*.tsv");
+        known_filter.set_name(Some("Word lists and Anki exports (.txt, .tsv)"));
+
+        let open_known = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![known_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::KnownWords)
+            });
+
+        let dictionary_filter = FileFilter::new();
+        dictionary_filter.add_pattern("*.xml");
+        dictionary_filter.add_pattern("JMdict*");
+        dictionary_filter.add_pattern("*.zip");
+        dictionary_filter.set_name(Some("JMdict or Yomitan dictionaries (.xml, .zip)"));
+
+        let open_dictionary = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![dictionary_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Dictionary)
+            });
+
+        let frequency_filter = FileFilter::new();
+        frequency_filter.add_pattern("*.zip");
+        frequency_filter.add_pattern("*.txt");
+        frequency_filter.add_pattern("*.tsv");
+        frequency_filter.add_pattern("*.csv");
+        frequency_filter.set_name(Some("Frequency lists (.zip, .txt, .tsv, .csv)"));
+
+        let open_frequency = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![frequency_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Frequency)
+            });
+
         let model = AppModel {
             sensitive: true,
             prefix: EntryBuffer::new(Some("MyAudiobook")),
             open_srt,
             open_audio,
+            open_image,
+            image_path: PathBuf::from(""),
+            open_epub,
+            epub_path: PathBuf::from(""),
+            book_furigana: false,
+            book_text: false,
+            generate_readings: false,
+            open_translation,
+            translation_path: PathBuf::from(""),
             buffer: gtk::TextBuffer::new(None),
             image: ImageMode::None,
+            has_cover: false,
+            has_video: false,
+            has_subtitles: false,
+            subtitle_track: EntryBuffer::new(None::<&str>),
+            cover: None,
+            image_settings: ImageSettings::default(),
+            process_images: true,
             audio_ext: None,
+            audio_track: EntryBuffer::new(None::<&str>),
             srt_path: PathBuf::from(""),
             audio_path: PathBuf::from(""),
             show_button: false,
             offset_before: 0.0,
             offset_after: 0.0,
+            max_length: 0.0,
+            split_on_silence: false,
+            snap_window: 0.0,
+            max_tail: 0.0,
+            trim_silence: false,
+            correction: None,
+            apply_correction: true,
+            lines: FactoryVecDeque::new(gtk::ListBox::default(), sender.input_sender()),
+            filter_pattern: EntryBuffer::new(None::<&str>),
+            filter_min_chars: 0.0,
+            filter_min_duration: 0.0,
+            filter_max_duration: 0.0,
+            filter_non_speech: false,
+            open_known,
+            known_path: PathBuf::from(""),
+            known_column: 1.0,
+            known_unknown: 1.0,
+            known_words: None,
+            open_dictionary,
+            dictionary_path: PathBuf::from(""),
+            open_frequency,
+            frequency_path: PathBuf::from(""),
+            order_by_difficulty: false,
+            dedup: None,
+            dedup_history: false,
+            keep_formatting: true,
+            tags: EntryBuffer::new(None::<&str>),
+            metadata: false,
             worker: AsyncHandler::builder()
                 .detach_worker(())
                 .forward(sender.input_sender(), identity),
         };
 
+        let lines_box = model.lines.widget();
         let widgets = view_output!();
 
         ComponentParts { model, widgets }
@@ -183,45 +640,101 @@ impl SimpleComponent for AppModel {
             }
             AppInMsg::SetImageMode(mode) => {
                 self.image = mode;
+                self.show_button = self.can_start();
+            }
+            AppInMsg::SetProcessImages(val) => {
+                self.process_images = val;
+            }
+            AppInMsg::SetBookFurigana(val) => {
+                self.book_furigana = val;
+            }
+            AppInMsg::SetBookText(val) => {
+                self.book_text = val;
+            }
+            AppInMsg::SetDedupMode(mode) => {
+                self.dedup = mode;
+            }
+            AppInMsg::SetMetadata(val) => {
+                self.metadata = val;
+            }
+            AppInMsg::SetKeepFormatting(val) => {
+                self.keep_formatting = val;
+            }
+            AppInMsg::SetDedupHistory(val) => {
+                self.dedup_history = val;
+            }
+            AppInMsg::SetOrderByDifficulty(val) => {
+                self.order_by_difficulty = val;
+            }
+            AppInMsg::SetGenerateReadings(val) => {
+                self.generate_readings = val;
+            }
+            AppInMsg::UpdateImageSize(val) => {
+                self.image_settings.max_dimension = val as u32;
+            }
+            AppInMsg::UpdateImageQuality(val) => {
+                self.image_settings.quality = val as u8;
+            }
+            AppInMsg::SetImageFormat(format) => {
+                self.image_settings.format = format;
+            }
+            AppInMsg::CoverReady(cover) => {
+                self.cover = cover;
+                sender.input(AppInMsg::StartConversion);
             }
             AppInMsg::StartConversion => {
-                self.worker
-                    .emit(AsyncHandlerInMsg::ConvertMP3(self.audio_path.clone()));
-            }
-            AppInMsg::StartAudioSplit => {
-                let args = MyArgs {
-                    prefix: self.prefix.text().to_string().replace(' ', "_"),
-                    audiobook: self.audio_path.clone(),
-                    subtitle: self.srt_path.clone(),
-                    start_offset: self.offset_before as i32,
-                    end_offset: self.offset_after as i32,
-                };
-                self.worker
-                    .emit(AsyncHandlerInMsg::SplitAudio(args, self.audio_path.clone()))
+                self.worker.emit(AsyncHandlerInMsg::ConvertMP3(
+                    self.audio_path.clone(),
+                    self.audio_stream(),
+                ));
             }
-
-            AppInMsg::StartGenDeck => self.worker.emit(AsyncHandlerInMsg::GenDeck(
-                self.prefix.text().to_string().replace(' ', "_"),
-                self.srt_path.clone(),
-                self.image == ImageMode::Extract
+            AppInMsg::StartAudioSplit => self.worker.emit(AsyncHandlerInMsg::SplitAudio(
+                self.args(),
+                self.audio_path.clone(),
             )),
 
+            AppInMsg::StartGenDeck(lines) => {
+                let source = match self.image {
+                    ImageMode::Extract | ImageMode::Custom => self
+                        .cover
+                        .clone()
+                        .map_or(ImageSource::None, ImageSource::Cover),
+                    ImageMode::Frames => ImageSource::Frames(self.audio_path.clone()),
+                    ImageMode::Epub => {
+                        ImageSource::Epub(self.epub_path.clone(), self.audio_path.clone())
+                    }
+                    ImageMode::None => ImageSource::None,
+                };
+                self.worker.emit(AsyncHandlerInMsg::GenDeck(
+                    self.prefix(),
+                    lines,
+                    source,
+                    self.image_settings(),
+                    self.deck_options(),
+                ))
+            }
+
             AppInMsg::Start => {
                 self.sensitive = false;
                 match self.image {
                     ImageMode::Extract => {
                         self.worker.emit(AsyncHandlerInMsg::GenImage(
                             self.audio_path.clone(),
-                            self.prefix.text().to_string(),
+                            self.prefix(),
+                            self.image_settings(),
+                        ));
+                    }
+                    ImageMode::Custom => {
+                        self.worker.emit(AsyncHandlerInMsg::CustomImage(
+                            self.image_path.clone(),
+                            self.prefix(),
+                            self.image_settings(),
                         ));
                     }
-                    _ => {
-                        sender.input(AppInMsg::StartConversion);
-                    } // ImageMode::None => todo!(),
-                    // ImageMode::Custom => todo!(),
+                    ImageMode::None | ImageMode::Frames | ImageMode::Epub => {
+                        sender.input(AppInMsg::CoverReady(None));
+                    }
                 };
-
-                //TODO handle custom cover file
             }
             AppInMsg::UpdateOffset(dir, val) => match dir {
                 OffsetDirection::Before => {
@@ -231,26 +744,158 @@ impl SimpleComponent for AppModel {
                     self.offset_after = val;
                 }
             },
+            AppInMsg::UpdateMaxLength(val) => {
+                self.max_length = val;
+            }
+            AppInMsg::SetSplitOnSilence(val) => {
+                self.split_on_silence = val;
+            }
+            AppInMsg::UpdateSnapWindow(val) => {
+                self.snap_window = val;
+            }
+            AppInMsg::UpdateMaxTail(val) => {
+                self.max_tail = val;
+            }
+            AppInMsg::SetTrimSilence(val) => {
+                self.trim_silence = val;
+            }
+            AppInMsg::AnalyseSync => {
+                self.sensitive = false;
+                self.worker.emit(AsyncHandlerInMsg::AnalyseSync(
+                    self.srt_path.clone(),
+                    self.audio_path.clone(),
+                ));
+            }
+            AppInMsg::SyncProposal(correction) => {
+                self.correction = correction;
+                self.sensitive = true;
+            }
+            AppInMsg::SetApplyCorrection(val) => {
+                self.apply_correction = val;
+            }
+            AppInMsg::Preview => {
+                self.sensitive = false;
+                self.worker.emit(AsyncHandlerInMsg::Preview(self.args()));
+            }
+            AppInMsg::PreviewReady(prepared) => {
+                let mut lines = self.lines.guard();
+                lines.clear();
+                for line in prepared.clips.into_iter().zip(prepared.originals) {
+                    lines.push_back(line);
+                }
+                self.sensitive = true;
+            }
+            AppInMsg::SaveLines => {
+                let mut path = self.srt_path.clone();
+                path.set_extension("edited.srt");
+                let msg =
+                    match Subtitles::new_from_vec(self.saved_lines()).write_to_file(&path, None) {
+                        Ok(()) => format!("Saved the edited lines to {}\n", path.to_string_lossy()),
+                        Err(err) => format!("Could not save the edited lines: {err}\n"),
+                    };
+                sender.input(AppInMsg::UpdateBuffer(msg, false));
+            }
+            AppInMsg::UpdateFilterLimit(limit, val) => match limit {
+                FilterLimit::MinChars => self.filter_min_chars = val,
+                FilterLimit::MinDuration => self.filter_min_duration = val,
+                FilterLimit::MaxDuration => self.filter_max_duration = val,
+                FilterLimit::KnownColumn => {
+                    self.known_column = val;
+                    self.load_known_words(&sender);
+                }
+                FilterLimit::Unknown => {
+                    self.known_unknown = val;
+                    if let Some(known) = &mut self.known_words {
+                        known.unknown = val as usize;
+                    }
+                }
+            },
+            AppInMsg::SetFilterNonSpeech(val) => {
+                self.filter_non_speech = val;
+            }
+            AppInMsg::DiscardLines => {
+                self.lines.guard().clear();
+            }
+            AppInMsg::PlayLine(index) => {
+                if let Some(line) = self.lines.get(index.current_index()) {
+                    self.worker.emit(AsyncHandlerInMsg::PlayClip(
+                        line.sub.clone(),
+                        self.audio_path.clone(),
+                        self.audio_stream(),
+                    ));
+                }
+            }
+            AppInMsg::ExtractSubtitles => {
+                let subtitles = probe::text_subtitles(&probe::streams(&self.audio_path));
+                match probe::pick(&subtitles, &self.subtitle_track.text()) {
+                    Some(stream) => {
+                        self.sensitive = false;
+                        self.worker.emit(AsyncHandlerInMsg::ExtractSubtitles(
+                            self.audio_path.clone(),
+                            stream.index,
+                        ));
+                    }
+                    None => sender.input(AppInMsg::UpdateBuffer(
+                        String::from("No such subtitle track.\n"),
+                        false,
+                    )),
+                }
+            }
+            AppInMsg::SubtitlesExtracted(path) => {
+                if let Some(path) = path {
+                    self.srt_path = path;
+                    self.correction = None;
+                    self.lines.guard().clear();
+                    self.show_button = self.can_start();
+                }
+                self.sensitive = true;
+            }
             AppInMsg::Recheck => {
-                self.show_button = self.prefix.length() > 0
-                    && !self.audio_path.as_os_str().is_empty()
-                    && !self.srt_path.as_os_str().is_empty();
+                self.show_button = self.can_start();
             }
             AppInMsg::Open(path, origin) => {
                 match origin {
                     DialogOrigin::Audio => {
-                        if path.extension().unwrap() == "m4b" {
+                        let ext = path.extension().unwrap_or_default();
+                        if ext == "m4b" {
                             self.audio_ext = Some(AudioExt::M4b);
                         } else {
                             self.audio_ext = Some(AudioExt::Mp3);
                         }
-                        self.audio_path = path
+                        let streams = probe::streams(&path);
+                        let subtitles = probe::text_subtitles(&streams);
+                        let mut tracks = format!(
+                            "Audio tracks:\n{}",
+                            probe::describe(&probe::streams_of(&streams, "audio"))
+                        );
+                        if !subtitles.is_empty() {
+                            tracks += &format!("Subtitle tracks:\n{}", probe::describe(&subtitles));
+                        }
+                        sender.input(AppInMsg::UpdateBuffer(tracks, true));
+                        self.has_subtitles = !subtitles.is_empty();
+                        self.has_cover = probe::cover(&streams).is_some();
+                        self.has_video = probe::video(&streams).is_some();
+                        self.audio_path = path;
+                        // Estimated and edited for the previous pair of files.
+                        self.correction = None;
+                        self.lines.guard().clear();
+                    }
+                    DialogOrigin::Srt => {
+                        self.srt_path = path;
+                        self.correction = None;
+                        self.lines.guard().clear();
+                    }
+                    DialogOrigin::Image => self.image_path = path,
+                    DialogOrigin::Epub => self.epub_path = path,
+                    DialogOrigin::Translation => self.translation_path = path,
+                    DialogOrigin::Dictionary => self.dictionary_path = path,
+                    DialogOrigin::Frequency => self.frequency_path = path,
+                    DialogOrigin::KnownWords => {
+                        self.known_path = path;
+                        self.load_known_words(&sender);
                     }
-                    DialogOrigin::Srt => self.srt_path = path,
                 };
-                self.show_button = self.prefix.length() > 0
-                    && !self.audio_path.as_os_str().is_empty()
-                    && !self.srt_path.as_os_str().is_empty();
+                self.show_button = self.can_start();
             }
         }
     }
@@ -299,7 +944,23 @@ impl SimpleComponent for AppModel {
                     gtk::Label {
                         #[watch]
                         set_label: &model.srt_path.to_string_lossy()
-                    }
+                    },
+                    gtk::Label {
+                        set_label: "or the subtitle track"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.subtitle_track,
+                        set_placeholder_text: Some("number or language"),
+                        #[watch]
+                        set_sensitive: model.has_subtitles,
+                    },
+                    gtk::Button::with_label("Extract") {
+                        #[watch]
+                        set_sensitive: model.has_subtitles,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AppInMsg::ExtractSubtitles);
+                        }
+                    },
                 },
                 gtk::Box {
                     #[watch]
@@ -315,6 +976,70 @@ impl SimpleComponent for AppModel {
                     gtk::Label {
                         #[watch]
                         set_label: &model.audio_path.to_string_lossy()
+                    },
+                    gtk::Label {
+                        set_label: "Audio track (number or language)"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.audio_track,
+                        set_placeholder_text: Some("default"),
+                    },
+                },
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Path to the book (.epub, optional)"
+                    },
+                    append = model.open_epub.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.epub_path.to_string_lossy()
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Furigana from its rubies"),
+                        set_active: false,
+                        #[watch]
+                        set_sensitive: !model.epub_path.as_os_str().is_empty(),
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetBookFurigana(btn.is_active()));
+                        }
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Use its text for the sentences"),
+                        set_active: false,
+                        #[watch]
+                        set_sensitive: !model.epub_path.as_os_str().is_empty(),
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetBookText(btn.is_active()));
+                        }
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Generate readings with the offline dictionary"),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetGenerateReadings(btn.is_active()));
+                        }
+                    },
+                },
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Translated subtitles (.srt, optional)"
+                    },
+                    append = model.open_translation.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.translation_path.to_string_lossy()
                     }
                 },
 
@@ -330,39 +1055,345 @@ impl SimpleComponent for AppModel {
                 gtk::Box {
                     set_orientation: gtk::Orientation::Vertical,
                     relm4::gtk::SpinButton::builder()
-                    .adjustment(&Adjustment::new(0.0, -500.0, 500.0, 1.0, 0.0, 0.0))
+                    .adjustment(&Adjustment::new(0.0, -600.0, 600.0, 0.001, 1.0, 0.0))
+                    .digits(3)
                     .build(){
                         connect_value_changed[sender] => move |x| {
                             sender.input(AppInMsg::UpdateOffset(OffsetDirection::Before, x.value()))
                     }},
                     gtk::Label {
-                            set_label: "Before (ms)"
+                            set_label: "Before (s)"
                         }
                 },
                 },
 
                 gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
                     set_spacing: 5,
                     set_margin_all: 5,
                     set_orientation: gtk::Orientation::Horizontal,
-                        #[watch]
-                        set_sensitive: model.sensitive,
                     gtk::Label {
-                        set_label: "Image:"
+                        set_label: "Split lines longer than (s, 0 = never)"
                     },
-                    append: group = &gtk::CheckButton {
-                        set_label: Some("None"),
-                        set_active: true,
-                        connect_toggled[sender] => move |btn| {
-                        if btn.is_active() {
-                            sender.input(AppInMsg::SetImageMode(ImageMode::None));
-                        }
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(0.0, 0.0, 600.0, 1.0, 0.0, 0.0))
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateMaxLength(x.value()))
+                    }},
+                    gtk::CheckButton {
+                        set_label: Some("Cut at pauses"),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetSplitOnSilence(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Snap clip boundaries to pauses within (ms, 0 = off)"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(0.0, 0.0, 2000.0, 50.0, 0.0, 0.0))
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateSnapWindow(x.value()))
+                    }},
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Max tail after a line (s, 0 = up to the next line)"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(0.0, 0.0, 30.0, 0.5, 0.0, 0.0))
+                    .digits(1)
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateMaxTail(x.value()))
+                    }},
+                    gtk::CheckButton {
+                        set_label: Some("Trim trailing silence"),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetTrimSilence(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Drop lines matching"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.filter_pattern,
+                        set_placeholder_text: Some("regex"),
+                        connect_changed => AppInMsg::Recheck,
+                    },
+                    gtk::Label {
+                        set_label: "or shorter than (chars)"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(0.0, 0.0, 100.0, 1.0, 0.0, 0.0))
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateFilterLimit(FilterLimit::MinChars, x.value()))
+                    }},
+                    gtk::Label {
+                        set_label: "or shorter than (s)"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(0.0, 0.0, 60.0, 0.1, 0.0, 0.0))
+                    .digits(1)
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateFilterLimit(FilterLimit::MinDuration, x.value()))
+                    }},
+                    gtk::Label {
+                        set_label: "or longer than (s, 0 = off)"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(0.0, 0.0, 600.0, 1.0, 0.0, 0.0))
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateFilterLimit(FilterLimit::MaxDuration, x.value()))
+                    }},
+                    gtk::CheckButton {
+                        set_label: Some("Drop non-speech lines (♪, (笑)...)"),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetFilterNonSpeech(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Tags"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.tags,
+                        set_placeholder_text: Some("separated by spaces"),
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Keep italics, bold and colours from the subtitles"),
+                        set_active: true,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetKeepFormatting(btn.is_active()));
+                        }
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Add timestamps, index and chapter fields"),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetMetadata(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Known words (optional)"
+                    },
+                    append = model.open_known.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.known_path.to_string_lossy()
+                    },
+                    gtk::Label {
+                        set_label: "column"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(1.0, 1.0, 50.0, 1.0, 0.0, 0.0))
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateFilterLimit(FilterLimit::KnownColumn, x.value()))
+                    }},
+                    gtk::Label {
+                        set_label: "keep lines with exactly this many unknown words"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(1.0, 0.0, 10.0, 1.0, 0.0, 0.0))
+                    .build(){
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateFilterLimit(FilterLimit::Unknown, x.value()))
+                    }},
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive && model.known_words.is_some(),
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Dictionary for the target words (JMdict or Yomitan, optional)"
+                    },
+                    append = model.open_dictionary.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.dictionary_path.to_string_lossy()
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Frequency list (Yomitan or text, optional)"
+                    },
+                    append = model.open_frequency.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.frequency_path.to_string_lossy()
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Order new cards by difficulty"),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetOrderByDifficulty(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Label {
+                        set_label: "Repeated sentences:"
+                    },
+                    append: keep = &gtk::CheckButton {
+                        set_label: Some("Keep"),
+                        set_active: true,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetDedupMode(None));
+                        }
+                    }
+                    },
+                    append = &gtk::CheckButton {
+                        set_label: Some("Skip"),
+                        set_group: Some(&keep),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetDedupMode(Some(DedupMode::Skip)));
+                        }
+                    }
+                    },
+                    append = &gtk::CheckButton {
+                        set_label: Some("Merge clips"),
+                        set_group: Some(&keep),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetDedupMode(Some(DedupMode::Merge)));
+                        }
+                    }
+                    },
+                    append = &gtk::CheckButton {
+                        set_label: Some("Tag"),
+                        set_group: Some(&keep),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetDedupMode(Some(DedupMode::Tag)));
+                        }
+                    }
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Also against previous decks"),
+                        set_active: false,
+                        #[watch]
+                        set_sensitive: model.dedup.is_some(),
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetDedupHistory(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    #[watch]
+                    set_sensitive: model.sensitive && model.show_button,
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    gtk::Button::with_label("Detect offset and drift") {
+                        connect_clicked[sender] => move |_| {
+                            sender.input(AppInMsg::AnalyseSync);
+                        }
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.correction.map_or(String::from("No correction"), |c| c.to_string()),
+                    },
+                    gtk::CheckButton {
+                        set_label: Some("Apply"),
+                        set_active: true,
+                        #[watch]
+                        set_sensitive: model.correction.is_some(),
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetApplyCorrection(btn.is_active()));
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                        #[watch]
+                        set_sensitive: model.sensitive,
+                    gtk::Label {
+                        set_label: "Image:"
+                    },
+                    append: group = &gtk::CheckButton {
+                        set_label: Some("None"),
+                        set_active: true,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetImageMode(ImageMode::None));
+                        }
                     }
                     },
-                    //TODO if file ext = m4b
                     append = &gtk::CheckButton {
-                        set_label: Some("Extract from m4b"),
+                        set_label: Some("Extract from the audio file"),
                         set_active: false,
+                        #[watch]
+                        set_sensitive: model.has_cover,
                         set_group: Some(&group),
                         connect_toggled[sender] => move |btn| {
                         if btn.is_active() {
@@ -370,20 +1401,148 @@ impl SimpleComponent for AppModel {
                         }
                     }
                     },
-                    // append = &gtk::CheckButton {
-                    //     set_sensitive: false,
-                    //     set_label: Some("From file"),
-                    //     set_group: Some(&group),
-                    //     set_active: false,
-                    //     connect_toggled[sender] => move |btn| {
-                    //     if btn.is_active() {
-                    //         sender.input(AppInMsg::SetImageMode(ImageMode::Custom));
-                    //     }
-                    // }
-                    // },
+                    append = &gtk::CheckButton {
+                        set_label: Some("From file"),
+                        set_group: Some(&group),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetImageMode(ImageMode::Custom));
+                        }
+                    }
+                    },
+                    append = model.open_image.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.image_path.to_string_lossy()
+                    },
+                    append = &gtk::CheckButton {
+                        set_label: Some("Screenshot per line"),
+                        set_group: Some(&group),
+                        set_active: false,
+                        #[watch]
+                        set_sensitive: model.has_video,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetImageMode(ImageMode::Frames));
+                        }
+                    }
+                    },
+                    append = &gtk::CheckButton {
+                        set_label: Some("EPUB illustrations"),
+                        set_group: Some(&group),
+                        set_active: false,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetImageMode(ImageMode::Epub));
+                        }
+                    }
+                    },
 
                 },
 
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::CheckButton {
+                        set_label: Some("Resize and compress images to"),
+                        set_active: true,
+                        connect_toggled[sender] => move |btn| {
+                            sender.input(AppInMsg::SetProcessImages(btn.is_active()));
+                        }
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(800.0, 100.0, 4000.0, 50.0, 0.0, 0.0))
+                    .build(){
+                        #[watch]
+                        set_sensitive: model.process_images,
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateImageSize(x.value()))
+                    }},
+                    gtk::Label {
+                        set_label: "px, quality"
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(80.0, 1.0, 100.0, 1.0, 0.0, 0.0))
+                    .build(){
+                        #[watch]
+                        set_sensitive: model.process_images,
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateImageQuality(x.value()))
+                    }},
+                    append: jpeg = &gtk::CheckButton {
+                        set_label: Some("JPEG"),
+                        set_active: true,
+                        #[watch]
+                        set_sensitive: model.process_images,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetImageFormat(ImageFormat::Jpeg));
+                        }
+                    }
+                    },
+                    append = &gtk::CheckButton {
+                        set_label: Some("WebP"),
+                        set_active: false,
+                        set_group: Some(&jpeg),
+                        #[watch]
+                        set_sensitive: model.process_images,
+                        connect_toggled[sender] => move |btn| {
+                        if btn.is_active() {
+                            sender.input(AppInMsg::SetImageFormat(ImageFormat::Webp));
+                        }
+                    }
+                    },
+                },
+
+
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_margin_all: 5,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.show_button,
+
+                    gtk::Box {
+                        set_spacing: 5,
+                        set_orientation: gtk::Orientation::Horizontal,
+                        gtk::Button::with_label("Preview and edit lines") {
+                            connect_clicked[sender] => move |_| {
+                                sender.input(AppInMsg::Preview);
+                            }
+                        },
+                        gtk::Button::with_label("Save edited subtitles") {
+                            #[watch]
+                            set_sensitive: !model.lines.is_empty(),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(AppInMsg::SaveLines);
+                            }
+                        },
+                        gtk::Button::with_label("Discard edits") {
+                            #[watch]
+                            set_sensitive: !model.lines.is_empty(),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(AppInMsg::DiscardLines);
+                            }
+                        },
+                        gtk::Label {
+                            #[watch]
+                            set_label: if model.lines.is_empty() {
+                                "The deck will use the .srt file"
+                            } else {
+                                "The deck will use the lines below"
+                            },
+                        },
+                    },
+                    gtk::ScrolledWindow {
+                        set_min_content_height: 200,
+
+                        #[local_ref]
+                        lines_box -> gtk::ListBox {},
+                    },
+                },
 
                 append = if model.show_button {
                     gtk::Button::with_label("Generate Deck !") {
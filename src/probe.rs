@@ -0,0 +1,208 @@
+use std::{collections::HashMap, path::Path, process::Command};
+
+use serde_json::Value;
+
+fn create_command() -> Command {
+    if cfg!(unix) {
+        Command::new("ffprobe")
+    } else if cfg!(windows) {
+        Command::new("ffprobe.exe")
+    } else {
+        panic!("Unsupported OS possibly.")
+    }
+}
+
+/// Runs ffprobe on `path` with the given arguments and returns its standard output.
+fn run(path: &Path, args: &[&str]) -> Option<String> {
+    let output = create_command()
+        .args(["-v", "error"])
+        .args(args)
+        .arg(path)
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Length of the media file, in milliseconds.
+pub fn duration(path: &Path) -> Option<u32> {
+    let out = run(
+        path,
+        &[
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ],
+    )?;
+    let secs: f64 = out.trim().parse().ok()?;
+    Some((secs * 1000.0) as u32)
+}
+
+#[derive(Debug, Clone)]
+pub struct Stream {
+    pub index: usize,
+    /// "audio", "video", "subtitle"...
+    pub codec_type: String,
+    pub codec_name: String,
+    /// Cover art embedded as a video stream.
+    pub attached_pic: bool,
+    pub language: Option<String>,
+    pub title: Option<String>,
+}
+
+pub fn streams(path: &Path) -> Vec<Stream> {
+    let Some(out) = run(path, &["-show_streams", "-of", "json"]) else {
+        return Vec::new();
+    };
+    let json: Value = serde_json::from_str(&out).unwrap_or_default();
+    json["streams"]
+        .as_array()
+        .map(|streams| {
+            streams
+                .iter()
+                .map(|s| Stream {
+                    index: s["index"].as_u64().unwrap_or(0) as usize,
+                    codec_type: s["codec_type"].as_str().unwrap_or("").to_string(),
+                    codec_name: s["codec_name"].as_str().unwrap_or("").to_string(),
+                    attached_pic: s["disposition"]["attached_pic"].as_u64() == Some(1),
+                    language: s["tags"]["language"].as_str().map(String::from),
+                    title: s["tags"]["title"].as_str().map(String::from),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Subtitle codecs ffmpeg can turn into SubRip.
+const TEXT_SUBTITLES: &[&str] = &["subrip", "ass", "ssa", "mov_text", "webvtt", "text"];
+
+/// Streams of the given type, in order, cover art excluded.
+pub fn streams_of(streams: &[Stream], codec_type: &str) -> Vec<Stream> {
+    streams
+        .iter()
+        .filter(|s| s.codec_type == codec_type && !s.attached_pic)
+        .cloned()
+        .collect()
+}
+
+/// Embedded subtitle streams that hold text rather than pictures.
+pub fn text_subtitles(streams: &[Stream]) -> Vec<Stream> {
+    streams_of(streams, "subtitle")
+        .into_iter()
+        .filter(|s| TEXT_SUBTITLES.contains(&s.codec_name.as_str()))
+        .collect()
+}
+
+/// Picks a stream by its position in `streams` or by its language tag.
+pub fn pick(streams: &[Stream], wanted: &str) -> Option<Stream> {
+    let wanted = wanted.trim();
+    match wanted.parse::<usize>() {
+        Ok(n) => streams.get(n),
+        Err(_) => streams
+            .iter()
+            .find(|s| s.language.as_deref() == Some(wanted)),
+    }
+    .cloned()
+}
+
+/// One line per stream, numbered by position, for the log.
+pub fn describe(streams: &[Stream]) -> String {
+    streams
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            format!(
+                "{}: {} ({}) {}\n",
+                i,
+                s.language.as_deref().unwrap_or("und"),
+                s.codec_name,
+                s.title.as_deref().unwrap_or("")
+            )
+        })
+        .collect()
+}
+
+/// The embedded cover art, if the file has any.
+pub fn cover(streams: &[Stream]) -> Option<Stream> {
+    streams.iter().find(|s| s.attached_pic).cloned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    /// In ms.
+    pub start: u32,
+    /// In ms.
+    pub end: u32,
+    pub title: String,
+}
+
+pub fn chapters(path: &Path) -> Vec<Chapter> {
+    let Some(out) = run(path, &["-show_chapters", "-of", "json"]) else {
+        return Vec::new();
+    };
+    let json: Value = serde_json::from_str(&out).unwrap_or_default();
+    let ms = |v: &Value| {
+        v.as_str()
+            .and_then(|s| s.parse::<f64>().ok())
+            .map_or(0, |secs| (secs * 1000.0) as u32)
+    };
+    json["chapters"]
+        .as_array()
+        .map(|chapters| {
+            chapters
+                .iter()
+                .map(|c| Chapter {
+                    start: ms(&c["start_time"]),
+                    end: ms(&c["end_time"]),
+                    title: c["tags"]["title"].as_str().unwrap_or("").to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The chapter playing at `ms`, with its position in the list.
+pub fn chapter_at(chapters: &[Chapter], ms: u32) -> Option<(usize, &Chapter)> {
+    chapters
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, c)| c.start <= ms)
+}
+
+/// Tags of the whole file, keys lowercased.
+pub fn format_tags(path: &Path) -> HashMap<String, String> {
+    let Some(out) = run(path, &["-show_entries", "format_tags", "-of", "json"]) else {
+        return HashMap::new();
+    };
+    let json: Value = serde_json::from_str(&out).unwrap_or_default();
+    json["format"]["tags"]
+        .as_object()
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|(k, v)| Some((k.to_lowercase(), v.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Who reads the audiobook. Audible and most taggers put it in the composer field.
+pub fn narrator(path: &Path) -> Option<String> {
+    let tags = format_tags(path);
+    ["narrator", "composer", "performer"]
+        .iter()
+        .find_map(|key| tags.get(*key))
+        .filter(|n| !n.trim().is_empty())
+        .cloned()
+}
+
+/// The first real video stream, cover art excluded.
+pub fn video(streams: &[Stream]) -> Option<Stream> {
+    streams
+        .iter()
+        .find(|s| s.codec_type == "video" && !s.attached_pic)
+        .cloned()
+}
@@ -0,0 +1,88 @@
+use std::{path::Path, process::Command};
+
+use regex::Regex;
+
+/// A pause in the audio, in milliseconds from the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Silence {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Silence {
+    pub fn middle(&self) -> u32 {
+        self.start + (self.end - self.start) / 2
+    }
+}
+
+/// Runs ffmpeg's `silencedetect` filter over the whole file and collects the pauses it reports.
+pub fn detect(path: &Path, noise_db: f64, min_duration_ms: u32) -> Vec<Silence> {
+    let mut command = if cfg!(unix) {
+        Command::new("ffmpeg")
+    } else if cfg!(windows) {
+        Command::new("ffmpeg.exe")
+    } else {
+        panic!("Unsupported OS possibly.")
+    };
+    command.args([
+        "-hide_banner",
+        "-nostats",
+        "-i",
+        path.to_str().unwrap_or(""),
+        "-vn",
+        "-af",
+        &format!(
+            "silencedetect=noise={noise_db}dB:d={}",
+            f64::from(min_duration_ms) / 1000.0
+        ),
+        "-f",
+        "null",
+        "-",
+    ]);
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+    parse(&String::from_utf8_lossy(&output.stderr))
+}
+
+fn parse(log: &str) -> Vec<Silence> {
+    let start_re = Regex::new(r"silence_start: (-?[\d.]+)").unwrap();
+    let end_re = Regex::new(r"silence_end: ([\d.]+)").unwrap();
+    let mut silences = Vec::new();
+    let mut start = None;
+    for line in log.lines() {
+        if let Some(c) = start_re.captures(line) {
+            start = c[1].parse::<f64>().ok();
+        } else if let Some(c) = end_re.captures(line) {
+            if let (Some(s), Ok(e)) = (start.take(), c[1].parse::<f64>()) {
+                silences.push(Silence {
+                    start: (s.max(0.0) * 1000.0) as u32,
+                    end: (e * 1000.0) as u32,
+                });
+            }
+        }
+    }
+    silences
+}
+
+/// Returns the silences overlapping `[from, to]`, `silences` being sorted.
+pub fn between(silences: &[Silence], from: u32, to: u32) -> &[Silence] {
+    let first = silences.partition_point(|s| s.end < from);
+    let last = silences.partition_point(|s| s.start <= to);
+    &silences[first..last.max(first)]
+}
+
+/// Returns the silence closest to `at`, if one lies within `window` ms of it.
+pub fn nearest(silences: &[Silence], at: u32, window: u32) -> Option<Silence> {
+    between(silences, at.saturating_sub(window), at + window)
+        .iter()
+        .min_by_key(|s| {
+            if s.start <= at && at <= s.end {
+                0
+            } else {
+                s.start.abs_diff(at).min(s.end.abs_diff(at))
+            }
+        })
+        .copied()
+}
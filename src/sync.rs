@@ -0,0 +1,174 @@
+use crate::silence::Silence;
+
+/// Largest constant offset (in ms) looked for, in either direction.
+const MAX_OFFSET: i64 = 120_000;
+const COARSE_STEP: i64 = 100;
+/// A subtitle start matches a speech onset when they are this close (in ms).
+const TOLERANCE: i64 = 250;
+/// How many subtitle starts are used per section for the coarse search.
+const SAMPLE: usize = 300;
+
+/// Linear timing correction, `corrected = time * (1 + drift) + offset`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Correction {
+    pub offset_ms: f64,
+    pub drift: f64,
+    /// Share of the subtitle starts that landed on a speech onset once corrected.
+    pub matched: f64,
+}
+
+impl Correction {
+    pub fn apply(&self, ms: u32) -> u32 {
+        (f64::from(ms) * (1.0 + self.drift) + self.offset_ms)
+            .round()
+            .max(0.0) as u32
+    }
+}
+
+impl std::fmt::Display for Correction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "offset {:+.3}s, drift {:+.1}ms/h ({:.0}% of lines matched)",
+            self.offset_ms / 1000.0,
+            self.drift * 3_600_000.0,
+            self.matched * 100.0
+        )
+    }
+}
+
+/// Speech starts where a pause ends.
+pub fn onsets(silences: &[Silence]) -> Vec<i64> {
+    silences.iter().map(|s| i64::from(s.end)).collect()
+}
+
+fn closest(onsets: &[i64], at: i64) -> Option<i64> {
+    let i = onsets.partition_point(|&o| o < at);
+    [i.checked_sub(1), Some(i)]
+        .into_iter()
+        .flatten()
+        .filter_map(|i| onsets.get(i))
+        .min_by_key(|&&o| (o - at).abs())
+        .map(|&o| o - at)
+}
+
+fn count_matches(starts: &[i64], onsets: &[i64], predict: impl Fn(i64) -> i64) -> usize {
+    starts
+        .iter()
+        .filter(|&&s| closest(onsets, predict(s)).is_some_and(|d| d.abs() <= TOLERANCE))
+        .count()
+}
+
+/// Best constant offset for a section of the subtitles, by trying every step in range.
+fn coarse_offset(starts: &[i64], onsets: &[i64]) -> i64 {
+    let step = (starts.len() / SAMPLE).max(1);
+    let sample: Vec<i64> = starts.iter().step_by(step).copied().collect();
+    (-MAX_OFFSET / COARSE_STEP..=MAX_OFFSET / COARSE_STEP)
+        .map(|i| i * COARSE_STEP)
+        .max_by_key(|&offset| {
+            (
+                count_matches(&sample, onsets, |s| s + offset),
+                -offset.abs(),
+            )
+        })
+        .unwrap_or(0)
+}
+
+/// Least squares fit of `onset = a * start + b` over the starts that fall within `window`
+/// of the current prediction.
+fn fit(starts: &[i64], onsets: &[i64], a: f64, b: f64, window: i64) -> Option<(f64, f64)> {
+    let pairs: Vec<(f64, f64)> = starts
+        .iter()
+        .filter_map(|&s| {
+            let predicted = (s as f64 * a + b).round() as i64;
+            closest(onsets, predicted)
+                .filter(|d| d.abs() <= window)
+                .map(|d| (s as f64, (predicted + d) as f64))
+        })
+        .collect();
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let var: f64 = pairs.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if var == 0.0 {
+        return Some((1.0, mean_y - mean_x));
+    }
+    let cov: f64 = pairs.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let a = cov / var;
+    Some((a, mean_y - a * mean_x))
+}
+
+/// Estimates the offset and drift between subtitle start times and speech onsets, both
+/// in ms and sorted. The first and last quarters of the book each give a coarse offset,
+/// which is then refined by regression over every line.
+pub fn estimate(starts: &[u32], onsets: &[i64]) -> Option<Correction> {
+    if starts.len() < 8 || onsets.is_empty() {
+        return None;
+    }
+    let starts: Vec<i64> = starts.iter().map(|&s| i64::from(s)).collect();
+    let quarter = starts.len() / 4;
+    let head = &starts[..quarter];
+    let tail = &starts[starts.len() - quarter..];
+    let (x1, y1) = (head[head.len() / 2], coarse_offset(head, onsets));
+    let (x2, y2) = (tail[tail.len() / 2], coarse_offset(tail, onsets));
+
+    let mut a = if x2 > x1 {
+        1.0 + (y2 - y1) as f64 / (x2 - x1) as f64
+    } else {
+        1.0
+    };
+    let mut b = (x1 + y1) as f64 - a * x1 as f64;
+    for window in [4 * TOLERANCE, 2 * TOLERANCE, TOLERANCE] {
+        (a, b) = fit(&starts, onsets, a, b, window)?;
+    }
+
+    let matched = count_matches(&starts, onsets, |s| (s as f64 * a + b).round() as i64);
+    Some(Correction {
+        offset_ms: b,
+        drift: a - 1.0,
+        matched: matched as f64 / starts.len() as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts of 400 lines about 3s apart, with some irregularity.
+    fn starts() -> Vec<u32> {
+        (0..400u32).map(|i| i * 3000 + i * 7919 % 1000).collect()
+    }
+
+    #[test]
+    fn estimate_recovers_offset_and_drift() {
+        let truth = Correction {
+            offset_ms: 2500.0,
+            drift: 0.0001,
+            matched: 1.0,
+        };
+        let starts = starts();
+        let onsets: Vec<i64> = starts.iter().map(|&s| i64::from(truth.apply(s))).collect();
+        let found = estimate(&starts, &onsets).unwrap();
+        assert!((found.offset_ms - truth.offset_ms).abs() < 50.0, "{found}");
+        assert!((found.drift - truth.drift).abs() < 0.00001, "{found}");
+        assert!(found.matched > 0.95, "{found}");
+    }
+
+    #[test]
+    fn estimate_finds_negative_offsets() {
+        let starts = starts();
+        let onsets: Vec<i64> = starts.iter().map(|&s| i64::from(s) - 1200).collect();
+        let found = estimate(&starts, &onsets).unwrap();
+        assert!((found.offset_ms + 1200.0).abs() < 50.0, "{found}");
+        assert!(found.drift.abs() < 0.00001, "{found}");
+    }
+
+    #[test]
+    fn estimate_needs_enough_data() {
+        assert_eq!(estimate(&starts()[..5], &[0, 1000, 2000]), None);
+        assert_eq!(estimate(&starts(), &[]), None);
+    }
+}
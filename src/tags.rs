@@ -0,0 +1,28 @@
+/// Lowercase letters and digits, with runs of anything else turned into one `_`, so
+/// it can be used in an Anki tag.
+pub fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+    slug.trim_end_matches('_').to_string()
+}
+
+/// Tenth of the book the time falls in, such as `position::30-40`.
+pub fn position(ms: u32, duration: u32) -> String {
+    let tenth = (u64::from(ms) * 10 / u64::from(duration.max(1))).min(9);
+    format!("position::{}-{}", tenth * 10, tenth * 10 + 10)
+}
+
+/// Tags typed by the user, separated by spaces or commas.
+pub fn parse(text: &str) -> Vec<String> {
+    text.split([' ', ',', '\t'])
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use srtlib::{Subtitle, Subtitles};
+
+use crate::converter::timestamp_to_ms;
+
+fn span(sub: &Subtitle) -> (u32, u32) {
+    (
+        timestamp_to_ms(&sub.start_time),
+        timestamp_to_ms(&sub.end_time),
+    )
+}
+
+/// Lines of the translated subtitles, sorted.
+pub fn load(path: &Path) -> Vec<Subtitle> {
+    let Ok(subs) = Subtitles::parse_from_file(path, Some("utf8")) else {
+        return Vec::new();
+    };
+    let mut subs = subs.to_vec();
+    subs.sort();
+    subs
+}
+
+/// Translation of each line, from the translated lines it overlaps with.
+/// A translated line goes with every line that covers at least half of the shorter of
+/// the two, so one line may get several translations joined and one translation may
+/// be shared by several lines. Lines with no such match take the translated line they
+/// overlap the most, if any.
+pub fn align(lines: &[Subtitle], translated: &[Subtitle]) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| {
+            let (start, end) = span(line);
+            let overlaps: Vec<(&Subtitle, u32, u32)> = translated
+                .iter()
+                .take_while(|t| span(t).0 < end)
+                .filter_map(|t| {
+                    let (t_start, t_end) = span(t);
+                    let overlap = end.min(t_end).saturating_sub(start.max(t_start));
+                    let shorter = end.saturating_sub(start).min(t_end.saturating_sub(t_start));
+                    (overlap > 0).then_some((t, overlap, shorter))
+                })
+                .collect();
+            let mut matched: Vec<&Subtitle> = overlaps
+                .iter()
+                .filter(|(_, overlap, shorter)| overlap * 2 >= *shorter)
+                .map(|(t, _, _)| *t)
+                .collect();
+            if matched.is_empty() {
+                matched.extend(
+                    overlaps
+                        .iter()
+                        .max_by_key(|(_, o, _)| *o)
+                        .map(|(t, _, _)| *t),
+                );
+            }
+            matched
+                .iter()
+                .map(|t| t.text.replace('\n', " "))
+                .collect::<Vec<String>>()
+                .join(" ")
+        })
+        .collect()
+}
@@ -1,4 +1,6 @@
+use std::fmt::format;
 use std::{
+    collections::HashSet,
     fs,
     io::Read,
     path::PathBuf,
@@ -7,24 +9,112 @@ use std::{
     thread,
     time::{SystemTime, UNIX_EPOCH},
 };
-use std::fmt::format;
 
 use genanki_rs::{Deck, Field, Model, Note, Package, Template};
+use itertools::Itertools;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use regex::Regex;
-use relm4::{ComponentSender, Worker};
 use relm4::gtk::cairo::Path;
-use srtlib::Subtitles;
+use relm4::{ComponentSender, Worker};
+use srtlib::{Subtitle, Subtitles};
 
-use crate::{converter, AppInMsg, AudioExt};
+use crate::{
+    align,
+    book::Book,
+    converter,
+    dedup::{self, DedupMode, Duplicate},
+    dictionary::Dictionary,
+    frequency::{self, Frequencies},
+    furigana, html,
+    images::{ImageSettings, ImageSource},
+    known::KnownWords,
+    probe, silence, sync, tags, translation, AppInMsg, AudioExt,
+};
 
 pub struct AsyncHandler;
 
+/// What goes into the notes besides the clip, the image and the sentence.
+#[derive(Debug, Clone, Default)]
+pub struct DeckOptions {
+    /// Subtitles in another language, for the Translation field.
+    pub translation: Option<PathBuf>,
+    /// EPUB of the same book.
+    pub book: Option<PathBuf>,
+    /// Take the SentenceFurigana field from the book's rubies.
+    pub book_furigana: bool,
+    /// Use the book's text for the Sentence field where it can be found.
+    pub book_text: bool,
+    /// Fill SentenceFurigana, where the book gave nothing, and Reading with the
+    /// offline analyser.
+    pub generate_readings: bool,
+    /// Puts the unknown words of each line in TargetWord and in its tags.
+    pub known_words: Option<KnownWords>,
+    /// JMdict XML or Yomitan zip defining the target words.
+    pub dictionary: Option<PathBuf>,
+    /// Word frequency list for the difficulty fields and tags.
+    pub frequencies: Option<PathBuf>,
+    /// Adds the notes easiest first instead of in book order.
+    pub order_by_difficulty: bool,
+    /// What to do with repeated sentences, None keeps them all.
+    pub dedup: Option<DedupMode>,
+    /// Also look for the sentences in the decks generated before.
+    pub dedup_history: bool,
+    /// Keep the italics, bold and colours of the subtitles rather than plain text.
+    pub keep_formatting: bool,
+    /// The original audio file, for its chapters and tags.
+    pub audio: PathBuf,
+    /// Added to every note.
+    pub tags: Vec<String>,
+    /// Fill the Start, End, Index and Chapter fields, the first three with the srt line
+    /// as it is in the file.
+    pub metadata: bool,
+}
+
+/// Everything worked out for a note besides its clip and image.
+#[derive(Debug, Clone, Default)]
+struct NoteExtras {
+    translation: String,
+    furigana: String,
+    sentence_reading: String,
+    unknown: Vec<String>,
+    word: String,
+    word_reading: String,
+    definition: String,
+    stats: Option<frequency::Stats>,
+    /// Words missing from the known words, or from the frequency list without those.
+    unknown_count: usize,
+    /// Later lines with the same sentence, whose clips go on this note.
+    merged: Vec<usize>,
+    skipped: bool,
+    chapter: String,
+    tags: Vec<String>,
+}
+
+impl NoteExtras {
+    /// Lines with fewer unknown words come first, then those with more common words.
+    fn difficulty(&self) -> (usize, u32, u32) {
+        let stats = self.stats.unwrap_or_default();
+        (self.unknown_count, stats.rarest, stats.average)
+    }
+}
+
 #[derive(Debug)]
 pub enum AsyncHandlerInMsg {
-    GenImage(PathBuf, String),
-    GenDeck(String, PathBuf, bool),
-    ConvertMP3(PathBuf),
+    GenImage(PathBuf, String, Option<ImageSettings>),
+    CustomImage(PathBuf, String, Option<ImageSettings>),
+    GenDeck(
+        String,
+        converter::Lines,
+        ImageSource,
+        Option<ImageSettings>,
+        DeckOptions,
+    ),
+    ConvertMP3(PathBuf, Option<usize>),
     SplitAudio(converter::MyArgs, PathBuf),
+    AnalyseSync(PathBuf, PathBuf),
+    Preview(converter::MyArgs),
+    PlayClip(Subtitle, PathBuf, Option<usize>),
+    ExtractSubtitles(PathBuf, usize),
 }
 
 impl AsyncHandler {
@@ -44,34 +134,409 @@ impl AsyncHandler {
             .unwrap();
     }
 
-    fn gen_image(&self, path: PathBuf, prefix: &str, sender: &ComponentSender<Self>) {
+    /// Writes the cover art embedded in the audio file next to it, re-encoded with
+    /// `settings`, or keeping its format when there are none.
+    /// Returns the name of the written file, or None when there is no usable art.
+    fn gen_image(
+        &self,
+        path: PathBuf,
+        prefix: &str,
+        settings: Option<ImageSettings>,
+        sender: &ComponentSender<Self>,
+    ) -> Option<String> {
+        AsyncHandler::update_buffer("Creating cover file...", true, sender);
+        let Some(stream) = probe::cover(&probe::streams(&path)) else {
+            AsyncHandler::update_buffer(
+                "No cover art in the audio file, the deck will have no image.\n",
+                false,
+                sender,
+            );
+            return None;
+        };
         let mut command = AsyncHandler::create_command();
         command.args([
             "-y",
             "-i",
-            &path.as_os_str().to_str().unwrap_or(""),
-            "-an",
-            "-vcodec",
-            "copy",
-            &format!("{}.jpg", prefix),
+            path.as_os_str().to_str().unwrap_or(""),
+            "-map",
+            &format!("0:{}", stream.index),
         ]);
+        let file = match settings {
+            Some(settings) => {
+                let file = settings.file_name(prefix);
+                command.args(settings.encode_args(&file));
+                file
+            }
+            None => {
+                let (ext, copy) = match stream.codec_name.as_str() {
+                    "mjpeg" => ("jpg", true),
+                    "png" => ("png", true),
+                    "webp" => ("webp", true),
+                    _ => ("jpg", false),
+                };
+                let file = format!("{}.{}", prefix, ext);
+                if copy {
+                    command.args(["-c", "copy"]);
+                }
+                command.args(["-frames:v", "1", &file]);
+                file
+            }
+        };
+        match command.output() {
+            Ok(output) if output.status.success() => {
+                AsyncHandler::update_buffer("Done!\n", false, sender);
+                Some(file)
+            }
+            _ => {
+                AsyncHandler::update_buffer(
+                    "Could not extract the cover, the deck will have no image.\n",
+                    false,
+                    sender,
+                );
+                None
+            }
+        }
+    }
+
+    /// Prepares a user chosen image as the cover packaged with the deck, re-encoded with
+    /// `settings`, or copied as is when there are none.
+    fn custom_image(
+        &self,
+        path: PathBuf,
+        prefix: &str,
+        settings: Option<ImageSettings>,
+        sender: &ComponentSender<Self>,
+    ) -> Option<String> {
         AsyncHandler::update_buffer("Creating cover file...", true, sender);
-        let child = command.output().unwrap();
-        AsyncHandler::update_buffer("Done!\n", false, sender);
+        let written = match settings {
+            Some(settings) => {
+                let file = settings.file_name(prefix);
+                let mut command = AsyncHandler::create_command();
+                command
+                    .args(["-y", "-i", path.as_os_str().to_str().unwrap_or("")])
+                    .args(settings.encode_args(&file));
+                command
+                    .output()
+                    .is_ok_and(|o| o.status.success())
+                    .then_some(file)
+            }
+            None => {
+                let ext = path.extension().unwrap_or_default().to_string_lossy();
+                let file = format!("{}.{}", prefix, ext.to_lowercase());
+                fs::copy(&path, &file).is_ok().then_some(file)
+            }
+        };
+        match written {
+            Some(file) => {
+                AsyncHandler::update_buffer("Done!\n", false, sender);
+                Some(file)
+            }
+            None => {
+                AsyncHandler::update_buffer("Could not convert the image!\n", false, sender);
+                None
+            }
+        }
+    }
+
+    /// Grabs a frame at the middle of every line. Returns the file of each line's image.
+    fn frame_images(
+        &self,
+        subs: &[Subtitle],
+        video: PathBuf,
+        prefix: &str,
+        settings: ImageSettings,
+        sender: &ComponentSender<Self>,
+    ) -> Vec<Option<String>> {
+        let Some(stream) = probe::video(&probe::streams(&video)) else {
+            AsyncHandler::update_buffer(
+                "No video stream to take screenshots from.\n",
+                false,
+                sender,
+            );
+            return vec![None; subs.len()];
+        };
+        AsyncHandler::update_buffer("Taking screenshots...\n", false, sender);
+        subs.par_iter()
+            .map(|sub| {
+                let start = converter::timestamp_to_ms(&sub.start_time);
+                let end = converter::timestamp_to_ms(&sub.end_time).max(start);
+                let middle = f64::from(start + (end - start) / 2) / 1000.0;
+                let file =
+                    settings.file_name(&format!("./gen/{}/{}-{}", prefix, prefix, sub.num - 1));
+                let mut command = AsyncHandler::create_command();
+                command
+                    .args([
+                        "-y",
+                        "-loglevel",
+                        "error",
+                        "-ss",
+                        &format!("{:.3}", middle),
+                        "-i",
+                        video.as_os_str().to_str().unwrap_or(""),
+                        "-map",
+                        &format!("0:{}", stream.index),
+                    ])
+                    .args(settings.encode_args(&file));
+                command
+                    .output()
+                    .is_ok_and(|o| o.status.success())
+                    .then_some(file)
+            })
+            .collect()
+    }
+
+    /// Extracts the illustrations of the EPUB and gives every line the latest one reached
+    /// in the audio at its start. Returns the file of each line's image.
+    fn epub_images(
+        &self,
+        subs: &[Subtitle],
+        epub: PathBuf,
+        audio: PathBuf,
+        prefix: &str,
+        settings: Option<ImageSettings>,
+        sender: &ComponentSender<Self>,
+    ) -> Vec<Option<String>> {
+        let mut book = match Book::open(&epub) {
+            Ok(book) => book,
+            Err(err) => {
+                AsyncHandler::update_buffer(
+                    &format!("Could not read the EPUB: {}\n", err),
+                    false,
+                    sender,
+                );
+                return vec![None; subs.len()];
+            }
+        };
+        AsyncHandler::update_buffer("Extracting illustrations...\n", false, sender);
+        let duration = probe::duration(&audio).unwrap_or_else(|| {
+            subs.last()
+                .map_or(0, |s| converter::timestamp_to_ms(&s.end_time))
+        });
+        let times = book.illustration_times(&probe::chapters(&audio), duration);
+
+        let mut files: Vec<(u32, String)> = Vec::with_capacity(times.len());
+        for (k, (time, path)) in times.into_iter().enumerate() {
+            let Some(bytes) = book.resource(&path) else {
+                continue;
+            };
+            let ext = path.extension().unwrap_or_default().to_string_lossy();
+            let raw = format!("./gen/{}/{}-ill-{}.{}", prefix, prefix, k, ext);
+            if fs::write(&raw, bytes).is_err() {
+                continue;
+            }
+            let file = match settings {
+                Some(settings) => {
+                    let file = settings
+                        .file_name(&format!("./gen/{}/{}-illustration-{}", prefix, prefix, k));
+                    let mut command = AsyncHandler::create_command();
+                    command
+                        .args(["-y", "-i", &raw])
+                        .args(settings.encode_args(&file));
+                    if command.output().is_ok_and(|o| o.status.success()) {
+                        file
+                    } else {
+                        raw
+                    }
+                }
+                None => raw,
+            };
+            files.push((time, file));
+        }
+        AsyncHandler::update_buffer(
+            &format!("{} illustrations placed\n", files.len()),
+            false,
+            sender,
+        );
+
+        subs.iter()
+            .map(|sub| {
+                let start = converter::timestamp_to_ms(&sub.start_time);
+                files
+                    .iter()
+                    .rev()
+                    .find(|(time, _)| *time <= start)
+                    .map(|(_, file)| file.clone())
+            })
+            .collect()
+    }
+
+    fn line_images(
+        &self,
+        subs: &[Subtitle],
+        source: ImageSource,
+        prefix: &str,
+        settings: Option<ImageSettings>,
+        sender: &ComponentSender<Self>,
+    ) -> Vec<Option<String>> {
+        match source {
+            ImageSource::None => vec![None; subs.len()],
+            ImageSource::Cover(cover) => vec![Some(cover); subs.len()],
+            ImageSource::Frames(video) => {
+                self.frame_images(subs, video, prefix, settings.unwrap_or_default(), sender)
+            }
+            ImageSource::Epub(epub, audio) => {
+                self.epub_images(subs, epub, audio, prefix, settings, sender)
+            }
+        }
+    }
+
+    fn analyse_sync(
+        &self,
+        srt_path: PathBuf,
+        audio_path: PathBuf,
+        sender: &ComponentSender<AsyncHandler>,
+    ) -> Option<sync::Correction> {
+        AsyncHandler::update_buffer(
+            "Looking for speech in the audio, this'll take a few minutes...\n",
+            true,
+            sender,
+        );
+        let mut subs = Subtitles::parse_from_file(srt_path, Some("utf8"))
+            .unwrap()
+            .to_vec();
+        subs.sort();
+        let starts: Vec<u32> = subs
+            .iter()
+            .map(|s| converter::timestamp_to_ms(&s.start_time))
+            .collect();
+        let silences = silence::detect(&audio_path, -35.0, 250);
+        let correction = sync::estimate(&starts, &sync::onsets(&silences));
+        match &correction {
+            Some(c) => {
+                AsyncHandler::update_buffer(&format!("Proposed correction: {c}\n"), false, sender)
+            }
+            None => AsyncHandler::update_buffer(
+                "Could not match the subtitles against the audio.\n",
+                false,
+                sender,
+            ),
+        }
+        correction
+    }
+
+    fn preview(
+        &self,
+        args: converter::MyArgs,
+        sender: &ComponentSender<AsyncHandler>,
+    ) -> converter::Lines {
+        AsyncHandler::update_buffer("Computing clips...\n", true, sender);
+        let (tx, rx): (Sender<String>, Receiver<String>) = mpsc::channel();
+        let lines = converter::prepare(&args, &tx);
+        for msg in rx.try_iter() {
+            AsyncHandler::update_buffer(&msg, false, sender);
+        }
+        AsyncHandler::update_buffer(&format!("{} lines\n", lines.clips.len()), false, sender);
+        lines
+    }
+
+    fn play_clip(
+        &self,
+        sub: Subtitle,
+        audio_path: PathBuf,
+        track: Option<usize>,
+        sender: &ComponentSender<AsyncHandler>,
+    ) {
+        if sub.start_time >= sub.end_time {
+            AsyncHandler::update_buffer("This line has an empty time range.\n", false, sender);
+            return;
+        }
+        let _ = fs::create_dir_all("./gen");
+        // Matroska takes any audio codec, so the clip can be copied whatever the input is.
+        let clip = "./gen/preview.mka";
+        let mut command = AsyncHandler::create_command();
+        command
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-vn",
+                "-y",
+                "-i",
+                audio_path.as_os_str().to_str().unwrap_or(""),
+            ])
+            .args(
+                track
+                    .map(|t| ["-map".to_string(), format!("0:{}", t)])
+                    .into_iter()
+                    .flatten(),
+            )
+            .args(converter::clip_args(&sub, clip));
+        if !command.output().is_ok_and(|o| o.status.success()) {
+            AsyncHandler::update_buffer("Could not extract the clip.\n", false, sender);
+            return;
+        }
+        let player = if cfg!(windows) {
+            "ffplay.exe"
+        } else {
+            "ffplay"
+        };
+        if Command::new(player)
+            .args(["-nodisp", "-autoexit", "-loglevel", "error", clip])
+            .spawn()
+            .is_err()
+        {
+            AsyncHandler::update_buffer("Could not start ffplay.\n", false, sender);
+        }
+    }
+
+    /// Writes the embedded subtitle stream `index` of the media file next to it as SubRip.
+    fn extract_subtitles(
+        &self,
+        media_path: PathBuf,
+        index: usize,
+        sender: &ComponentSender<AsyncHandler>,
+    ) -> Option<PathBuf> {
+        let mut srt_path = media_path.clone();
+        srt_path.set_extension(format!("{}.srt", index));
+        let output = AsyncHandler::create_command()
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-y",
+                "-i",
+                media_path.as_os_str().to_str().unwrap_or(""),
+                "-map",
+                &format!("0:{}", index),
+                "-f",
+                "srt",
+                srt_path.as_os_str().to_str().unwrap_or(""),
+            ])
+            .output();
+        match output {
+            Ok(o) if o.status.success() => {
+                let count = Subtitles::parse_from_file(&srt_path, Some("utf8"))
+                    .map_or(0, |subs| subs.to_vec().len());
+                AsyncHandler::update_buffer(
+                    &format!(
+                        "Extracted {} lines to {}\n",
+                        count,
+                        srt_path.to_string_lossy()
+                    ),
+                    false,
+                    sender,
+                );
+                Some(srt_path)
+            }
+            _ => {
+                AsyncHandler::update_buffer("Could not extract the subtitles.\n", false, sender);
+                None
+            }
+        }
     }
 
     fn convert_mp3(
         &self,
         audio_path: PathBuf,
         // audio_ext: Option<crate::AudioExt>,
+        track: Option<usize>,
         sender: &ComponentSender<AsyncHandler>,
     ) {
         let regex = Regex::new(r"size=.* time=(.*?) .* speed=(.*x)").unwrap();
         let (tx, rx): (Sender<String>, Receiver<String>) = mpsc::channel();
         let thread_tx = tx.clone();
-        let audio_ext = audio_path.extension().unwrap_or_default();
         //TODO if can be removed probably
-        if audio_ext == "m4b" {
+        if converter::needs_conversion(&audio_path) {
             let mut converted_path = audio_path.clone();
             converted_path.set_extension("mp3");
             AsyncHandler::update_buffer(
@@ -88,10 +553,15 @@ impl AsyncHandler {
                 // "-y",
                 "-i",
                 audio_path.as_os_str().to_str().unwrap_or(""),
+            ]);
+            if let Some(track) = track {
+                command.args(["-map", &format!("0:{}", track)]);
+            }
+            command.args([
                 "-vn",
                 "-acodec",
                 "libmp3lame",
-                &converted_path.as_os_str().to_str().unwrap_or(""),
+                converted_path.as_os_str().to_str().unwrap_or(""),
             ]);
             let mut child = command.spawn().unwrap();
             let mut stderr = child.stderr.take().unwrap();
@@ -137,10 +607,10 @@ impl AsyncHandler {
         mut args: converter::MyArgs,
         path: PathBuf,
         sender: &ComponentSender<AsyncHandler>,
-    ) {
-        let audio_ext = path.extension().unwrap_or_default();
+    ) -> converter::Lines {
+        let converted = converter::needs_conversion(&path);
         // let path =
-        if audio_ext == "m4b" {
+        if converted {
             let mut converted_path = path.clone();
             converted_path.set_extension("mp3");
             args.audiobook = converted_path;
@@ -152,40 +622,300 @@ impl AsyncHandler {
 
         let (tx, rx): (Sender<String>, Receiver<String>) = mpsc::channel();
         let thread_tx = tx.clone();
-        thread::spawn(move || {
-            converter::process(args, thread_tx.clone());
+        // What prepare reported, kept above the progress line that replaces itself.
+        let mut report = String::new();
+        let handle = thread::spawn(move || {
+            let lines = match args.lines.take() {
+                Some(lines) => lines,
+                None => converter::prepare(&args, &thread_tx),
+            };
+            converter::process(args, &lines.clips, thread_tx.clone());
             thread_tx.send(String::from("STOP")).unwrap();
+            lines
         });
         loop {
             if let Ok(msg) = rx.recv() {
                 if msg == "STOP" {
-                    if audio_ext == "m4b" {
+                    if converted {
                         let mut converted_path = path.clone();
                         converted_path.set_extension("mp3");
                         // let _ = fs::remove_file(&converted_path);
                     }
                     AsyncHandler::update_buffer("Extracting done!", false, sender);
                     break;
+                } else if msg.ends_with("completed!\n") {
+                    AsyncHandler::update_buffer(&format!("{report}{msg}"), true, sender);
                 } else {
-                    AsyncHandler::update_buffer(&msg, true, sender);
+                    report += &msg;
+                    AsyncHandler::update_buffer(&msg, false, sender);
                 }
             }
         }
+        handle.join().unwrap()
     }
 
-    fn gen_deck(&self, prefix: &str, srt_path: PathBuf, img: bool, sender: &ComponentSender<AsyncHandler>) {
+    fn gen_deck(
+        &self,
+        prefix: &str,
+        lines: converter::Lines,
+        source: ImageSource,
+        settings: Option<ImageSettings>,
+        options: DeckOptions,
+        sender: &ComponentSender<AsyncHandler>,
+    ) {
+        let converter::Lines {
+            clips: mut subs,
+            originals,
+        } = lines;
+        let images = self.line_images(&subs, source.clone(), prefix, settings, sender);
+        let book = match options.book.as_deref().map(Book::open) {
+            Some(Ok(book)) => Some(book),
+            Some(Err(err)) => {
+                AsyncHandler::update_buffer(
+                    &format!("Could not read the book: {}\n", err),
+                    false,
+                    sender,
+                );
+                None
+            }
+            None => None,
+        };
+        let mut extras = vec![NoteExtras::default(); subs.len()];
+        if let Some(book) = book.as_ref().filter(|_| options.book_text) {
+            AsyncHandler::update_buffer("Matching the lines with the book...\n", false, sender);
+            let texts = align::book_text(&subs, &book.text);
+            let mut unsure = 0;
+            for ((sub, text), extra) in subs.iter_mut().zip(texts).zip(extras.iter_mut()) {
+                match text {
+                    Some(text) => sub.text = text,
+                    None => {
+                        unsure += 1;
+                        extra.tags.push(String::from("check_text"));
+                    }
+                }
+            }
+            AsyncHandler::update_buffer(
+                &format!(
+                    "{} lines could not be matched and were tagged check_text\n",
+                    unsure
+                ),
+                false,
+                sender,
+            );
+        }
+        if let Some(mode) = options.dedup {
+            let history = if options.dedup_history {
+                dedup::load_history()
+            } else {
+                HashSet::new()
+            };
+            let duplicates = dedup::find(&subs, &history);
+            for (i, duplicate) in duplicates.iter().enumerate() {
+                match (mode, duplicate) {
+                    (_, None) => {}
+                    (DedupMode::Tag, Some(Duplicate::Of(_))) => {
+                        extras[i].tags.push(String::from("duplicate"))
+                    }
+                    (DedupMode::Tag, Some(Duplicate::Seen)) => {
+                        extras[i].tags.push(String::from("seen_before"))
+                    }
+                    (DedupMode::Merge, Some(Duplicate::Of(first))) => {
+                        extras[*first].merged.push(i);
+                        extras[i].skipped = true;
+                    }
+                    (DedupMode::Skip | DedupMode::Merge, Some(_)) => extras[i].skipped = true,
+                }
+            }
+            AsyncHandler::update_buffer(
+                &format!(
+                    "{} repeated lines, {} already in previous decks\n",
+                    duplicates
+                        .iter()
+                        .filter(|d| matches!(d, Some(Duplicate::Of(_))))
+                        .count(),
+                    duplicates
+                        .iter()
+                        .filter(|d| **d == Some(Duplicate::Seen))
+                        .count()
+                ),
+                false,
+                sender,
+            );
+        }
+        if let Some(path) = &options.translation {
+            // Both files share the timing of the srt, before any correction or padding.
+            let translated = translation::load(path);
+            for (extra, translation) in extras
+                .iter_mut()
+                .zip(translation::align(&originals, &translated))
+            {
+                extra.translation = translation;
+            }
+        }
+        if let Some(book) = book.as_ref().filter(|_| options.book_furigana) {
+            AsyncHandler::update_buffer(
+                &format!("Adding the readings of {} rubies...\n", book.rubies.len()),
+                false,
+                sender,
+            );
+            for (extra, furigana) in extras
+                .iter_mut()
+                .zip(furigana::from_rubies(&subs, &book.rubies))
+            {
+                extra.furigana = furigana;
+            }
+        }
+        let frequencies = match options.frequencies.as_deref().map(Frequencies::load) {
+            Some(Ok(frequencies)) => {
+                AsyncHandler::update_buffer(
+                    &format!("Loaded the ranks of {} words\n", frequencies.len()),
+                    false,
+                    sender,
+                );
+                Some(frequencies)
+            }
+            Some(Err(err)) => {
+                AsyncHandler::update_buffer(
+                    &format!("Could not read the frequency list: {}\n", err),
+                    false,
+                    sender,
+                );
+                None
+            }
+            None => None,
+        };
+        if options.generate_readings || options.known_words.is_some() || frequencies.is_some() {
+            match furigana::Analyser::new() {
+                Ok(analyser) => {
+                    AsyncHandler::update_buffer("Analysing the sentences...\n", false, sender);
+                    for (sub, extra) in subs.iter().zip(extras.iter_mut()) {
+                        let text = html::plain(&sub.text);
+                        if options.generate_readings {
+                            let (generated, kana) = analyser.readings(&text);
+                            if !extra.furigana.contains('[') {
+                                extra.furigana = generated;
+                            }
+                            extra.sentence_reading = kana;
+                        }
+                        if let Some(known) = &options.known_words {
+                            extra.unknown = known.unknown_words(&analyser, &text);
+                            extra.unknown_count = extra.unknown.len();
+                            extra
+                                .tags
+                                .extend(extra.unknown.iter().map(|w| format!("unknown::{}", w)));
+                        }
+                        if let Some(frequencies) = &frequencies {
+                            let stats = frequencies.stats(&analyser.words(&text));
+                            extra.tags.push(stats.tag());
+                            extra.stats = Some(stats);
+                            if options.known_words.is_none() {
+                                extra.unknown_count = stats.unranked;
+                            }
+                        }
+                    }
+                }
+                Err(err) => AsyncHandler::update_buffer(
+                    &format!("Could not load the morphological analyser: {}\n", err),
+                    false,
+                    sender,
+                ),
+            }
+        }
+        if let Some(path) = &options.dictionary {
+            let wanted: HashSet<String> = extras
+                .iter()
+                .filter_map(|e| e.unknown.first().cloned())
+                .collect();
+            AsyncHandler::update_buffer("Loading the dictionary...\n", false, sender);
+            match Dictionary::load(path, &wanted) {
+                Ok(dictionary) => {
+                    AsyncHandler::update_buffer(
+                        &format!(
+                            "Found {} of {} target words\n",
+                            dictionary.len(),
+                            wanted.len()
+                        ),
+                        false,
+                        sender,
+                    );
+                    for extra in extras.iter_mut() {
+                        let Some(entry) = extra.unknown.first().and_then(|w| dictionary.lookup(w))
+                        else {
+                            continue;
+                        };
+                        extra.word = entry.word.clone();
+                        extra.word_reading = entry.reading.clone();
+                        extra.definition = entry.definition.clone();
+                    }
+                }
+                Err(err) => AsyncHandler::update_buffer(
+                    &format!("Could not load the dictionary: {}\n", err),
+                    false,
+                    sender,
+                ),
+            }
+        }
+        let chapters = probe::chapters(&options.audio);
+        let duration = probe::duration(&options.audio).unwrap_or_else(|| {
+            subs.last()
+                .map_or(0, |s| converter::timestamp_to_ms(&s.end_time))
+        });
+        let mut common = vec![format!("book::{}", tags::slug(prefix))];
+        common.extend(
+            probe::narrator(&options.audio).map(|n| format!("narrator::{}", tags::slug(&n))),
+        );
+        common.extend(options.tags.iter().cloned());
+        for (sub, extra) in subs.iter().zip(extras.iter_mut()) {
+            let start = converter::timestamp_to_ms(&sub.start_time);
+            extra.tags.extend(common.iter().cloned());
+            if let Some((i, chapter)) = probe::chapter_at(&chapters, start) {
+                let title = tags::slug(&chapter.title);
+                extra.chapter = chapter.title.clone();
+                extra.tags.push(if title.is_empty() {
+                    format!("chapter::{:03}", i + 1)
+                } else {
+                    format!("chapter::{:03}_{}", i + 1, title)
+                });
+            }
+            extra.tags.push(tags::position(start, duration));
+        }
+        let mut order: Vec<usize> = (0..subs.len()).filter(|&i| !extras[i].skipped).collect();
+        if options.order_by_difficulty {
+            // New cards are shown in the order their notes were added.
+            order.sort_by_key(|&i| extras[i].difficulty());
+        }
         AsyncHandler::update_buffer("Converting to apkg...", false, sender);
+        // A new id for every change to the fields, since Anki refuses to import notes
+        // into an existing note type whose fields differ.
         let model = Model::new(
-            170655988728,
-            "audiobook to srs",
+            170655988729,
+            "audiobook to srs v2",
             vec![
                 Field::new("Audio"),
                 Field::new("Image"),
                 Field::new("Sentence"),
+                Field::new("Translation"),
+                Field::new("SentenceFurigana"),
+                Field::new("SentenceReading"),
+                Field::new("TargetWord"),
+                Field::new("Word"),
+                Field::new("Reading"),
+                Field::new("Definition"),
+                Field::new("RarestRank"),
+                Field::new("AverageRank"),
+                Field::new("UnknownCount"),
+                Field::new("Start"),
+                Field::new("End"),
+                Field::new("Index"),
+                Field::new("Chapter"),
             ],
-            vec![Template::new("Card 1")
-                .qfmt("{{Sentence}}")
-                .afmt(r#"{{FrontSide}}<hr id="answer">{{Audio}} {{Image}}"#)],
+            vec![Template::new("Card 1").qfmt("{{Sentence}}").afmt(concat!(
+                r#"{{FrontSide}}<hr id="answer">{{Audio}} {{Image}}"#,
+                "{{#TargetWord}}<br>{{TargetWord}}{{/TargetWord}}",
+                "{{#Definition}}<br>{{Word}}【{{Reading}}】<br>{{Definition}}{{/Definition}}",
+                "{{#SentenceFurigana}}<br>{{furigana:SentenceFurigana}}{{/SentenceFurigana}}",
+                "{{#Translation}}<br>{{Translation}}{{/Translation}}",
+            ))],
         );
         let now = SystemTime::now();
         let timestamp = now.duration_since(UNIX_EPOCH).unwrap().as_millis();
@@ -198,52 +928,100 @@ impl AsyncHandler {
             ),
         );
 
-        let subs = Subtitles::parse_from_file(srt_path, Some("utf8"))
-            .unwrap()
-            .to_vec();
-
         let mut files: Vec<String> = Vec::with_capacity(subs.len() + 100);
 
         // subs.sort();
-        let img_string = if img {
-            format!("<img src=\"{}.jpg\">", prefix)
-        } else {
-            String::from("")
-        };
-
-        for sub in subs {
-            files.push(format!("./gen/{}/{}-{}.mp3", prefix, prefix, sub.num - 1));
+        for i in order {
+            let (sub, image, extra) = (&subs[i], &images[i], &extras[i]);
+            let stats = extra.stats.map(|stats| {
+                (
+                    stats.rarest.to_string(),
+                    stats.average.to_string(),
+                    extra.unknown_count.to_string(),
+                )
+            });
+            let (rarest, average, unknown_count) = stats.unwrap_or_default();
+            let (start, end, index, chapter) = if options.metadata {
+                let original = &originals[i];
+                (
+                    original.start_time.to_string().replace(',', "."),
+                    original.end_time.to_string().replace(',', "."),
+                    // Padded so that Anki sorts the notes in book order.
+                    format!("{:05}", original.num),
+                    extra.chapter.clone(),
+                )
+            } else {
+                Default::default()
+            };
+            let mut audio = String::new();
+            for clip in std::iter::once(i).chain(extra.merged.iter().copied()) {
+                let num = subs[clip].num - 1;
+                files.push(format!("./gen/{}/{}-{}.mp3", prefix, prefix, num));
+                audio += &format!("[sound:{}-{}.mp3]", prefix, num);
+            }
+            let img_string = match image {
+                Some(image) => format!(
+                    "<img src=\"{}\">",
+                    std::path::Path::new(image)
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                ),
+                None => String::from(""),
+            };
             deck.add_note(
-                Note::new(
+                Note::new_with_options(
                     model.clone(),
                     vec![
-                        &format!("[sound:{}-{}.mp3]", prefix, sub.num - 1),
+                        &audio,
                         &img_string,
-                        &sub.text,
+                        &html::to_html(&sub.text, options.keep_formatting),
+                        &html::to_html(&extra.translation, options.keep_formatting),
+                        &html::to_html(&extra.furigana, false),
+                        &html::to_html(&extra.sentence_reading, false),
+                        &extra.unknown.join(", "),
+                        &extra.word,
+                        &extra.word_reading,
+                        &extra.definition,
+                        &rarest,
+                        &average,
+                        &unknown_count,
+                        &start,
+                        &end,
+                        &index,
+                        &html::to_html(&chapter, false),
                     ],
+                    None,
+                    Some(extra.tags.iter().map(|t| t.as_str()).collect()),
+                    None,
                 )
-                    .unwrap(),
+                .unwrap(),
             );
         }
 
         let mut files2: Vec<&str> = files.iter().map(|s| &**s).collect();
-        let cover = format!("{}.jpg", prefix);
-        if img {
-            files2.push(&cover);
-        }
+        files2.extend(images.iter().flatten().map(|s| &**s).unique());
 
         let mut package = Package::new(vec![deck], files2).unwrap();
         package.write_to_file(&format!("{}.apkg", prefix)).unwrap();
         AsyncHandler::update_buffer("Conversion to apkg done!!\n", true, sender);
+        if options.dedup_history {
+            let hashes = subs.iter().filter_map(|s| dedup::hash(&s.text));
+            if let Err(err) = dedup::save_history(hashes) {
+                AsyncHandler::update_buffer(
+                    &format!("Could not save the sentence history: {}\n", err),
+                    false,
+                    sender,
+                );
+            }
+        }
         AsyncHandler::update_buffer("Cleaning up..", false, sender);
         let _ = fs::remove_dir_all(format!("./gen/{}", prefix));
-        if img {
-            let _ = fs::remove_file(&cover);
+        if let ImageSource::Cover(cover) = &source {
+            let _ = fs::remove_file(cover);
         }
         AsyncHandler::update_buffer("..Done!", false, sender);
-        sender
-            .output(AppInMsg::Ended)
-            .unwrap();
+        sender.output(AppInMsg::Ended).unwrap();
     }
 }
 
@@ -258,18 +1036,42 @@ impl Worker for AsyncHandler {
 
     fn update(&mut self, msg: AsyncHandlerInMsg, sender: ComponentSender<Self>) {
         match msg {
-            AsyncHandlerInMsg::GenImage(path, prefix) => {
-                self.gen_image(path, &prefix, &sender);
-                sender.output(AppInMsg::StartConversion).unwrap();
+            AsyncHandlerInMsg::GenImage(path, prefix, settings) => {
+                let cover = self.gen_image(path, &prefix, settings, &sender);
+                sender.output(AppInMsg::CoverReady(cover)).unwrap();
+            }
+            AsyncHandlerInMsg::CustomImage(path, prefix, settings) => {
+                let cover = self.custom_image(path, &prefix, settings, &sender);
+                sender.output(AppInMsg::CoverReady(cover)).unwrap();
+            }
+            AsyncHandlerInMsg::GenDeck(prefix, lines, source, settings, options) => {
+                self.gen_deck(&prefix, lines, source, settings, options, &sender)
             }
-            AsyncHandlerInMsg::GenDeck(prefix, path, img) => self.gen_deck(&prefix, path, img, &sender),
             AsyncHandlerInMsg::SplitAudio(args, path) => {
-                self.split_audio(args, path, &sender);
-                sender.output(AppInMsg::StartGenDeck).unwrap();
+                let lines = self.split_audio(args, path, &sender);
+                sender.output(AppInMsg::StartGenDeck(lines)).unwrap();
+            }
+
+            AsyncHandlerInMsg::AnalyseSync(srt_path, audio_path) => {
+                let correction = self.analyse_sync(srt_path, audio_path, &sender);
+                sender.output(AppInMsg::SyncProposal(correction)).unwrap();
+            }
+
+            AsyncHandlerInMsg::Preview(args) => {
+                let lines = self.preview(args, &sender);
+                sender.output(AppInMsg::PreviewReady(lines)).unwrap();
+            }
+            AsyncHandlerInMsg::PlayClip(sub, audio_path, track) => {
+                self.play_clip(sub, audio_path, track, &sender)
+            }
+
+            AsyncHandlerInMsg::ExtractSubtitles(media_path, index) => {
+                let path = self.extract_subtitles(media_path, index, &sender);
+                sender.output(AppInMsg::SubtitlesExtracted(path)).unwrap();
             }
 
-            AsyncHandlerInMsg::ConvertMP3(audio_path) => {
-                self.convert_mp3(audio_path, &sender);
+            AsyncHandlerInMsg::ConvertMP3(audio_path, track) => {
+                self.convert_mp3(audio_path, track, &sender);
                 sender.output(AppInMsg::StartAudioSplit).unwrap();
             }
         }